@@ -3,6 +3,8 @@ MEMORY
   /* https://github.com/lulf/microbit-bsp/blob/main/examples/ble-nrf-softdevice/memory.x */
 MBR         : ORIGIN = 0x00000000, LENGTH = 4K
 SOFTDEVICE  : ORIGIN = 0x00001000, LENGTH = 114688
-FLASH       : ORIGIN = 0x0001C000, LENGTH = 405504
+FLASH       : ORIGIN = 0x0001C000, LENGTH = 401408
+/* 0x7E000..0x7F000 holds the bonds, see bonds.rs */
+/* the last page, 0x7F000..0x80000, holds the car config, see settings.rs */
 RAM         : ORIGIN = 0x2000afa8, LENGTH = 86104
 }
@@ -0,0 +1,90 @@
+//! Pack voltage on ring pin 2, through a divider, see `rcdrive::battery`
+//!
+//! The charge goes out through the battery service, the speed limit is read
+//! by the motor task every control period.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{error, info, warn};
+use embassy_nrf::{
+    bind_interrupts,
+    interrupt::{self, InterruptExt, Priority},
+    peripherals::{P0_04, SAADC},
+    saadc::{self, Saadc},
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Ticker};
+use rcdrive::battery::{BatteryMonitor, BatteryState};
+use rcproto::{BatteryConfig, Faults};
+
+use crate::failsafe::FAULTS;
+
+bind_interrupts!(struct Irqs {
+    SAADC => saadc::InterruptHandler;
+});
+
+/// slow enough to leave the adc idle, fast enough to cut a sagging pack
+const SAMPLE_PERIOD: Duration = Duration::from_millis(200);
+/// full scale of the internal 0.6V reference at gain 1/6
+const FULL_SCALE_MV: f32 = 3600.0;
+
+/// charge in percent, signalled with every sample
+pub static LEVEL: Signal<ThreadModeRawMutex, u8> = Signal::new();
+
+/// percent of full speed, stays at 100 without a battery divider
+static SPEED_LIMIT: AtomicU8 = AtomicU8::new(100);
+/// charge in percent, [`UNKNOWN`] until the first sample
+static CHARGE: AtomicU8 = AtomicU8::new(UNKNOWN);
+const UNKNOWN: u8 = u8::MAX;
+
+/// latest charge in percent, `None` without a battery divider or pack
+pub fn level() -> Option<u8> {
+    Some(CHARGE.load(Ordering::Relaxed)).filter(|l| *l != UNKNOWN)
+}
+
+/// fraction of full speed the battery allows
+pub fn speed_limit() -> f32 {
+    SPEED_LIMIT.load(Ordering::Relaxed) as f32 / 100.0
+}
+
+#[embassy_executor::task]
+pub async fn monitor_battery(adc: SAADC, pin: P0_04, config: BatteryConfig) {
+    let mut adc_config = saadc::Config::default();
+    adc_config.resolution = saadc::Resolution::_12BIT;
+    adc_config.oversample = saadc::Oversample::OVER8X;
+    let channel = saadc::ChannelConfig::single_ended(pin);
+
+    interrupt::SAADC.set_priority(Priority::P5);
+    let mut saadc = Saadc::new(adc, Irqs, adc_config, [channel]);
+    saadc.calibrate().await;
+
+    let mut monitor = BatteryMonitor::new(config);
+    let mut ticker = Ticker::every(SAMPLE_PERIOD);
+    loop {
+        let mut buf = [0; 1];
+        saadc.sample(&mut buf).await;
+        let pin_mv = buf[0].max(0) as f32 * FULL_SCALE_MV / 4096.0;
+        let before = monitor.state();
+        let state = monitor.update(pin_mv);
+        let pack_mv = monitor.pack_mv().unwrap_or_default();
+        if state != before {
+            match state {
+                BatteryState::Ok => info!("battery recovered at {}mV", pack_mv),
+                BatteryState::Low => warn!("battery low at {}mV, limiting speed", pack_mv),
+                BatteryState::Cutoff => {
+                    error!("battery flat at {}mV, cutting motors", pack_mv);
+                    FAULTS.raise(Faults::BATTERY_CUTOFF);
+                }
+                BatteryState::NoSensor => {
+                    warn!("no battery on the divider, {}mV at the pin", pin_mv)
+                }
+            }
+        }
+        SPEED_LIMIT.store((state.speed_limit() * 100.0) as u8, Ordering::Relaxed);
+        CHARGE.store(monitor.level().unwrap_or(UNKNOWN), Ordering::Relaxed);
+        if let Some(level) = monitor.level() {
+            LEVEL.signal(level);
+        }
+        ticker.next().await;
+    }
+}
@@ -3,16 +3,19 @@
 
 //! suggested reading: https://docs.silabs.com/bluetooth/4.0/general/adv-and-scanning/bluetooth-adv-data-basics
 
+use core::cell::Cell;
 use core::f32;
 use core::ops::Deref;
+use core::sync::atomic::{AtomicU16, Ordering};
 
 use embassy_executor::Spawner;
+use embassy_futures::select::select;
 use embassy_nrf::interrupt::Priority;
-use embassy_nrf::{bind_interrupts, peripherals::TWISPI0, saadc, twim};
-use embassy_time::Timer;
+use embassy_nrf::{bind_interrupts, peripherals::TWISPI0, twim};
+use embassy_sync::blocking_mutex;
+use embassy_time::{Duration, Timer};
 use heapless::Vec;
 // use nrf_softdevice::ble::gatt_server::{notify_value, Server};
-use array_concat::split_array;
 use defmt::{debug, error, info, println, trace, warn};
 use nrf_softdevice::ble::advertisement_builder::{
     AdvertisementDataType, Flag, LegacyAdvertisementBuilder, LegacyAdvertisementPayload,
@@ -25,60 +28,645 @@ use nrf_softdevice::{raw, Softdevice};
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+use rcproto::{
+    CarConfig, CompassState, ControlRequest, EStopState, Echo, Gear, LatencyReport, LineFollow,
+    LinkParams, MotionLimits, PidGains, ScriptCommand, VelocityCommand, CONFIG_LEN,
+    CONTROL_REQUEST_LEN, CONTROL_STATUS_LEN, ECHO_LEN, LATENCY_LEN, LINE_FOLLOW_LEN,
+    LINK_PARAMS_LEN, MOTION_LIMITS_LEN, MOTOR_ERRORS_LEN, PID_GAINS_LEN, POSE_LEN,
+    SCRIPT_COMMAND_MAX, SCRIPT_STATUS_LEN, UNIQUE_NAME_MAX, VELOCITY_LEN,
+};
+
+use crate::battery;
+use crate::bonds;
+use crate::compass::{self, ZERO_HEADING};
+use crate::control::{self, MAX_CONNECTIONS};
+use crate::display;
+use crate::encoder;
+use crate::estop;
+use crate::failsafe::{self, FAULTS};
+use crate::line;
+use crate::link;
+use crate::motor::{GEAR, MOTOR_ERRORS, POSE, RESET_POSE};
+use crate::script;
+use crate::settings::{self, SharedConfig, SharedFlash};
+use crate::sonar;
+use crate::sound::{self, Sound};
 use crate::Mutex;
+use crate::SharedGains;
+use crate::SharedLimits;
 use crate::SharedSpeed;
+use crate::SharedTimeout;
 use crate::ThreadModeRawMutex;
 
 #[nrf_softdevice::gatt_server]
 pub struct Server {
     pub rcar: RcCarService,
+    pub battery: BatteryService,
 }
 
+/// uuids must match `rcproto::SERVICE_UUID_STR` and friends
+///
+/// Everything writable needs a passkey bond, the softdevice turns away reads
+/// and writes of it over any other link before they get here, see
+/// [`needs_bond`].
 #[nrf_softdevice::gatt_service(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a30")]
 pub struct RcCarService {
-    ///speed forward m/s
-    #[characteristic(uuid = "2C09", write, read)]
-    target_velocity: [u8; 3 * 4],
+    /// encoded `rcproto::VelocityCommand`
+    #[characteristic(uuid = "2C09", write, read, security = "Mitm")]
+    target_velocity: [u8; VELOCITY_LEN],
+    /// encoded `rcproto::PidGains` of the wheel speed controller
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a31",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    pid_gains: [u8; PID_GAINS_LEN],
+    /// encoded `rcproto::MotionLimits` of the acceleration ramp
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a32",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    motion_limits: [u8; MOTION_LIMITS_LEN],
+    /// latched `rcproto::Faults`, any write clears them
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a33",
+        write,
+        read,
+        notify,
+        security = "Mitm"
+    )]
+    faults: u8,
+    /// ms without a velocity command before the car stops itself, see
+    /// `rcproto::failsafe_timeout` for the range
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a34",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    failsafe_timeout_ms: u16,
+    /// encoded `rcproto::CarConfig`, written to flash and applied on the next boot
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a35",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    config: [u8; CONFIG_LEN],
+    /// any write makes the current heading the zero of headless driving
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a36",
+        write,
+        security = "Mitm"
+    )]
+    zero_heading: u8,
+    /// encoded `rcproto::Pose` of the dead reckoning, any write resets it
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a37",
+        write,
+        read,
+        notify,
+        security = "Mitm"
+    )]
+    pose: [u8; POSE_LEN],
+    /// encoded `rcproto::MotorErrors`, failed motor outputs since boot
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a38", read, notify)]
+    motor_errors: [u8; MOTOR_ERRORS_LEN],
+    /// an encoded `rcproto::ScriptCommand`
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a39",
+        write,
+        security = "Mitm"
+    )]
+    script: Vec<u8, SCRIPT_COMMAND_MAX>,
+    /// encoded `rcproto::ScriptStatus` of the uploaded script
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3a", read, notify)]
+    script_status: [u8; SCRIPT_STATUS_LEN],
+    /// `rcproto::Gear` the car drives with
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3b",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    gear: u8,
+    /// mm to the obstacle ahead, `rcproto::NO_OBSTACLE` when there is none
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3c", read, notify)]
+    distance_mm: u16,
+    /// encoded `rcproto::LineFollow`, switches the line follow mode
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3d",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    line_follow: [u8; LINE_FOLLOW_LEN],
+    /// any write honks
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3e",
+        write,
+        security = "Mitm"
+    )]
+    horn: u8,
+    /// `rcproto::EStopState`, re-arming needs a centered joystick
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3f",
+        write,
+        read,
+        notify,
+        security = "Mitm"
+    )]
+    estop: u8,
+    /// an encoded `rcproto::ControlRequest`, claims or passes on driving
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a40",
+        write,
+        security = "Mitm"
+    )]
+    control: [u8; CONTROL_REQUEST_LEN],
+    /// encoded `rcproto::ControlStatus`, who drives
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a41", read, notify)]
+    control_status: [u8; CONTROL_STATUS_LEN],
+    /// encoded `rcproto::LinkParams`, notified to each client with its own
+    /// link once tuned, reads show the link tuned last
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a42", read, notify)]
+    link: [u8; LINK_PARAMS_LEN],
+    /// encoded `rcproto::Echo` of the latest velocity command, notified to
+    /// its sender
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a43", read, notify)]
+    echo: [u8; ECHO_LEN],
+    /// encoded `rcproto::LatencyReport`, the controller posts what it
+    /// measured from the echoes here for everybody else to read
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a44",
+        write,
+        read,
+        security = "Mitm"
+    )]
+    latency: [u8; LATENCY_LEN],
+    /// `rcproto::CompassState`, writing `Calibrating` spins the car in place
+    /// to calibrate the compass, `Uncalibrated` stops that
+    #[characteristic(
+        uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a45",
+        write,
+        read,
+        notify,
+        security = "Mitm"
+    )]
+    compass: u8,
 }
 
 impl RcCarService {}
 
+/// the standard battery service, so generic BLE tools show the charge too
+#[nrf_softdevice::gatt_service(uuid = "180f")]
+pub struct BatteryService {
+    /// rough charge in percent, see `rcdrive::battery::BatteryMonitor::level`
+    #[characteristic(uuid = "2a19", read, notify)]
+    battery_level: u8,
+}
+
 #[embassy_executor::task]
 pub async fn softdevice_task(sd: &'static Softdevice) {
     sd.run().await;
 }
 
-static CONN: Mutex<ThreadModeRawMutex, Option<Connection>> = Mutex::new(None);
+/// open connections by slot, see `control`
+static CONNS: Mutex<ThreadModeRawMutex, [Option<Connection>; MAX_CONNECTIONS]> =
+    Mutex::new([const { None }; MAX_CONNECTIONS]);
+
+/// writes that move the car or change its state, only taken over a bonded
+/// link, see `bonds`
+fn needs_bond(e: &ServerEvent) -> bool {
+    drives(e)
+        || matches!(
+            e,
+            // anyone bonded may stop the car, honk, clear its faults or claim it
+            ServerEvent::Rcar(
+                RcCarServiceEvent::EstopWrite(_)
+                    | RcCarServiceEvent::HornWrite(_)
+                    | RcCarServiceEvent::FaultsWrite(_)
+                    | RcCarServiceEvent::ControlWrite(_)
+                    | RcCarServiceEvent::LatencyWrite(_)
+            )
+        )
+}
+
+/// writes that move the car or change how it drives, only taken from the
+/// driver, see `control`
+fn drives(e: &ServerEvent) -> bool {
+    match e {
+        ServerEvent::Rcar(e) => match e {
+            RcCarServiceEvent::TargetVelocityWrite(_)
+            | RcCarServiceEvent::PidGainsWrite(_)
+            | RcCarServiceEvent::MotionLimitsWrite(_)
+            | RcCarServiceEvent::FailsafeTimeoutMsWrite(_)
+            | RcCarServiceEvent::ConfigWrite(_)
+            | RcCarServiceEvent::ZeroHeadingWrite(_)
+            | RcCarServiceEvent::PoseWrite(_)
+            | RcCarServiceEvent::ScriptWrite(_)
+            | RcCarServiceEvent::GearWrite(_)
+            | RcCarServiceEvent::LineFollowWrite(_)
+            | RcCarServiceEvent::CompassWrite(_) => true,
+            // anyone may stop the car
+            RcCarServiceEvent::EstopWrite(state) => *state == EStopState::Armed as u8,
+            _ => false,
+        },
+        ServerEvent::Battery(_) => false,
+    }
+}
 
-#[embassy_executor::task(pool_size = "1")]
-pub async fn gatt_server_task(server: &'static Server, target_speed: &'static SharedSpeed) {
+/// one per slot, see `control::MAX_CONNECTIONS`
+#[embassy_executor::task(pool_size = "3")]
+pub async fn gatt_server_task(
+    slot: usize,
+    conn: Connection,
+    server: &'static Server,
+    target_speed: &'static SharedSpeed,
+    gains: &'static SharedGains,
+    limits: &'static SharedLimits,
+    timeout: &'static SharedTimeout,
+    stored: &'static SharedConfig,
+) {
+    RECEIVED[slot].store(0, Ordering::Relaxed);
+    let mut tuned = conn.clone();
+    let tuning = async {
+        let params = link::tune(&mut tuned, slot).await.encode();
+        if let Err(e) = server.rcar.link_set(&params) {
+            warn!("failed to set link params: {}", e);
+        }
+        if let Err(e) = server.rcar.link_notify(&conn, &params) {
+            debug!("failed to notify link params: {}", e);
+        }
+    };
     {
-        let conn = {
-            let lock = CONN.lock().await;
-            lock.as_ref().unwrap().clone() // clone is used here so we can drop the lock
+        let serving = gatt_server::run(&conn, server, |e| {
+            if needs_bond(&e) && !bonds::is_bonded(&conn) {
+                debug!("slot {}: link not bonded, write ignored", slot);
+                return;
+            }
+            if drives(&e) && !control::may_drive(slot) {
+                debug!("slot {} doesn't drive, write ignored", slot);
+                return;
+            }
+            match e {
+                ServerEvent::Rcar(e) => match e {
+                    RcCarServiceEvent::TargetVelocityWrite(v_bytes) => {
+                        match VelocityCommand::decode(&v_bytes) {
+                            Ok(cmd) => {
+                                if estop::command(&cmd) {
+                                    trace!("set speed request {}", cmd);
+                                    target_speed.signal(cmd);
+                                } else {
+                                    trace!("emergency stop, speed request ignored");
+                                }
+                                echo(server, &conn, slot, &cmd);
+                            }
+                            Err(e) => warn!("bad velocity command: {}", e),
+                        }
+                    }
+                    RcCarServiceEvent::PidGainsWrite(bytes) => match PidGains::decode(&bytes) {
+                        Ok(g) => gains.signal(g),
+                        Err(e) => warn!("bad pid gains: {}", e),
+                    },
+                    RcCarServiceEvent::MotionLimitsWrite(bytes) => {
+                        match MotionLimits::decode(&bytes) {
+                            Ok(l) => limits.signal(l),
+                            Err(e) => warn!("bad motion limits: {}", e),
+                        }
+                    }
+                    RcCarServiceEvent::FaultsWrite(_) => {
+                        info!("faults cleared by client");
+                        FAULTS.clear();
+                    }
+                    RcCarServiceEvent::FaultsCccdWrite { notifications } => {
+                        debug!("fault notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::FailsafeTimeoutMsWrite(ms) => {
+                        match rcproto::failsafe_timeout(ms) {
+                            Ok(ms) => timeout.signal(ms),
+                            Err(e) => warn!("failsafe timeout of {}ms rejected: {}", ms, e),
+                        }
+                    }
+                    RcCarServiceEvent::ConfigWrite(bytes) => {
+                        match CarConfig::decode(&bytes) {
+                            Ok(c) if c.sonar && !sonar::fits(&c) => {
+                                warn!("config rejected, the sonar pins are taken in this build");
+                            }
+                            Ok(c) if !encoder::fits(&c) => {
+                                warn!("config rejected, this build has no encoder pins");
+                            }
+                            Ok(mut c) => {
+                                let pin = control::instructor_pin();
+                                c.instructor_pin = if control::may_set_pin(slot) {
+                                    c.written_pin(pin)
+                                } else {
+                                    // only the instructor gets to change it
+                                    pin
+                                };
+                                control::set_instructor_pin(c.instructor_pin);
+                                stored.signal(c);
+                                SHOWN.lock(|shown| shown.set(Some(c)));
+                            }
+                            Err(e) => warn!("bad config: {}", e),
+                        }
+                        // the pin that was just written is nobody else's business
+                        if let Some(c) = SHOWN.lock(Cell::get) {
+                            if let Err(e) = server.rcar.config_set(&hidden_pin(&c)) {
+                                warn!("failed to set config: {}", e);
+                            }
+                        }
+                    }
+                    RcCarServiceEvent::ZeroHeadingWrite(_) => ZERO_HEADING.signal(()),
+                    RcCarServiceEvent::PoseWrite(_) => RESET_POSE.signal(()),
+                    RcCarServiceEvent::PoseCccdWrite { notifications } => {
+                        debug!("pose notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::MotorErrorsCccdWrite { notifications } => {
+                        debug!("motor error notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::ScriptWrite(bytes) => match ScriptCommand::decode(&bytes) {
+                        Ok(command) => script::apply(&command),
+                        Err(e) => warn!("bad script command: {}", e),
+                    },
+                    RcCarServiceEvent::ScriptStatusCccdWrite { notifications } => {
+                        debug!("script status notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::GearWrite(g) => match Gear::try_from(g) {
+                        Ok(g) => GEAR.signal(g),
+                        Err(e) => warn!("bad gear: {}", e),
+                    },
+                    RcCarServiceEvent::DistanceMmCccdWrite { notifications } => {
+                        debug!("distance notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::LineFollowWrite(bytes) => match LineFollow::decode(&bytes) {
+                        Ok(params) => line::configure(params),
+                        Err(e) => warn!("bad line follow params: {}", e),
+                    },
+                    RcCarServiceEvent::HornWrite(_) => sound::play(Sound::Horn),
+                    RcCarServiceEvent::EstopWrite(state) => match EStopState::try_from(state) {
+                        Ok(EStopState::Stopped) => estop::trigger("client"),
+                        Ok(EStopState::Armed) => estop::rearm(),
+                        Err(e) => warn!("bad emergency stop state: {}", e),
+                    },
+                    RcCarServiceEvent::EstopCccdWrite { notifications } => {
+                        debug!("emergency stop notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::ControlWrite(bytes) => {
+                        match ControlRequest::decode(&bytes) {
+                            Ok(request) => match control::apply(slot, &request) {
+                                // nobody drives on with the last command of another
+                                Ok(true) => target_speed.signal(VelocityCommand::default()),
+                                Ok(false) => {}
+                                Err(e) => debug!("slot {}: {} refused: {}", slot, request, e),
+                            },
+                            Err(e) => warn!("bad control request: {}", e),
+                        }
+                    }
+                    RcCarServiceEvent::ControlStatusCccdWrite { notifications } => {
+                        debug!("control status notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::LinkCccdWrite { notifications } => {
+                        debug!("link notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::EchoCccdWrite { notifications } => {
+                        debug!("echo notifications: {}", notifications);
+                    }
+                    RcCarServiceEvent::LatencyWrite(bytes) => match LatencyReport::decode(&bytes) {
+                        Ok(report) => info!("slot {} measured {}", slot, report),
+                        Err(e) => warn!("bad latency report: {}", e),
+                    },
+                    RcCarServiceEvent::CompassWrite(state) => match CompassState::try_from(state) {
+                        Ok(CompassState::Calibrating) => compass::calibrate(),
+                        Ok(CompassState::Uncalibrated) => compass::abort(),
+                        Ok(state) => warn!("compass can't be set {}", state),
+                        Err(e) => warn!("bad compass state: {}", e),
+                    },
+                    RcCarServiceEvent::CompassCccdWrite { notifications } => {
+                        debug!("compass notifications: {}", notifications);
+                    }
+                },
+                ServerEvent::Battery(e) => match e {
+                    BatteryServiceEvent::BatteryLevelCccdWrite { notifications } => {
+                        debug!("battery level notifications: {}", notifications);
+                    }
+                },
+            }
+        });
+        // a link that drops while tuning is cleaned up at once
+        let tuning = async {
+            tuning.await;
+            core::future::pending::<()>().await
         };
+        select(serving, tuning).await;
+        info!("connection in slot {} closed", slot);
+        display::show_passkey(None);
+        sound::play(Sound::Disconnected);
+    }
+    let mut conns = CONNS.lock().await;
+    conns[slot] = None;
+    display::set_connected(conns.iter().any(Option::is_some));
+    if control::disconnect(slot) {
+        failsafe::link_lost();
+    }
+}
+
+/// velocity commands received from the client in each slot since it
+/// connected, see `rcproto::Echo`
+static RECEIVED: [AtomicU16; MAX_CONNECTIONS] = [const { AtomicU16::new(0) }; MAX_CONNECTIONS];
+
+/// hands the sequence number and timestamp of `cmd` back to its sender
+fn echo(server: &Server, conn: &Connection, slot: usize, cmd: &VelocityCommand) {
+    let received = RECEIVED[slot]
+        .fetch_add(1, Ordering::Relaxed)
+        .wrapping_add(1);
+    let echo = Echo::of(cmd, received).encode();
+    if let Err(e) = server.rcar.echo_set(&echo) {
+        warn!("failed to set echo: {}", e);
+    }
+    if let Err(e) = server.rcar.echo_notify(conn, &echo) {
+        trace!("failed to notify echo: {}", e);
+    }
+}
 
-        gatt_server::run(&conn, server, |e| match e {
-            ServerEvent::Rcar(e) => match e {
-                RcCarServiceEvent::TargetVelocityWrite(v_bytes) => {
-                    let (x_bytes, y_bytes, z_bytes) = split_array!(v_bytes, 4, 4, 4);
-                    let x = f32::from_le_bytes(x_bytes);
-                    let y = f32::from_le_bytes(y_bytes);
-                    let z = f32::from_le_bytes(z_bytes);
-                    trace!("set speed request x:{} y:{} z:{}", x, y, z);
-                    target_speed.signal([x, y, z]);
-                }
-            },
-        })
-        .await;
-        info!("connection closed");
-    }
-    let mut lock = CONN.lock().await;
-    lock.take();
+/// the config the characteristic shows, put back over a write that doesn't
+/// decode
+static SHOWN: blocking_mutex::Mutex<ThreadModeRawMutex, Cell<Option<CarConfig>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+/// `config` with the instructor pin zeroed, for reading
+fn hidden_pin(config: &CarConfig) -> [u8; CONFIG_LEN] {
+    CarConfig {
+        instructor_pin: 0,
+        ..*config
+    }
+    .encode()
+}
+
+#[embassy_executor::task]
+pub async fn report_faults(server: &'static Server) {
+    loop {
+        let faults = FAULTS.changed().await;
+        if let Err(e) = server.rcar.faults_set(&faults.bits()) {
+            warn!("failed to set faults: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.faults_notify(conn, &faults.bits()) {
+                debug!("failed to notify faults: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_motor_errors(server: &'static Server) {
+    loop {
+        let errors = MOTOR_ERRORS.wait().await.encode();
+        if let Err(e) = server.rcar.motor_errors_set(&errors) {
+            warn!("failed to set motor errors: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.motor_errors_notify(conn, &errors) {
+                debug!("failed to notify motor errors: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_script(server: &'static Server) {
+    loop {
+        let status = script::STATUS.wait().await.encode();
+        if let Err(e) = server.rcar.script_status_set(&status) {
+            warn!("failed to set script status: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.script_status_notify(conn, &status) {
+                debug!("failed to notify script status: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_battery(server: &'static Server) {
+    let mut last = None;
+    loop {
+        let level = battery::LEVEL.wait().await;
+        // sampled far more often than the charge changes
+        if last == Some(level) {
+            continue;
+        }
+        last = Some(level);
+        if let Err(e) = server.battery.battery_level_set(&level) {
+            warn!("failed to set battery level: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.battery.battery_level_notify(conn, &level) {
+                debug!("failed to notify battery level: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_estop(server: &'static Server) {
+    loop {
+        let state = estop::STATE.wait().await as u8;
+        if let Err(e) = server.rcar.estop_set(&state) {
+            warn!("failed to set emergency stop: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.estop_notify(conn, &state) {
+                debug!("failed to notify emergency stop: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_compass(server: &'static Server) {
+    loop {
+        let state = compass::STATE.wait().await as u8;
+        if let Err(e) = server.rcar.compass_set(&state) {
+            warn!("failed to set compass state: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.compass_notify(conn, &state) {
+                debug!("failed to notify compass state: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_control(server: &'static Server) {
+    loop {
+        control::CHANGED.wait().await;
+        if let Err(e) = server
+            .rcar
+            .control_status_set(&control::status(None).encode())
+        {
+            warn!("failed to set control status: {}", e);
+        }
+        // everybody is told their own slot
+        for (slot, conn) in CONNS.lock().await.iter().enumerate() {
+            let Some(conn) = conn else { continue };
+            let status = control::status(Some(slot)).encode();
+            if let Err(e) = server.rcar.control_status_notify(conn, &status) {
+                debug!("failed to notify control status: {}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn report_distance(server: &'static Server) {
+    loop {
+        let distance = sonar::DISTANCE.wait().await;
+        if let Err(e) = server.rcar.distance_mm_set(&distance) {
+            warn!("failed to set distance: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.distance_mm_notify(conn, &distance) {
+                trace!("failed to notify distance: {}", e);
+            }
+        }
+    }
+}
+
+/// how often the pose is notified, slower than the control loop updates it
+const POSE_PERIOD: Duration = Duration::from_millis(100);
+
+#[embassy_executor::task]
+pub async fn report_pose(server: &'static Server) {
+    loop {
+        let pose = POSE.wait().await.encode();
+        if let Err(e) = server.rcar.pose_set(&pose) {
+            warn!("failed to set pose: {}", e);
+        }
+        for conn in CONNS.lock().await.iter().flatten() {
+            if let Err(e) = server.rcar.pose_notify(conn, &pose) {
+                trace!("failed to notify pose: {}", e);
+            }
+        }
+        Timer::after(POSE_PERIOD).await;
+    }
+}
+
+/// the 64 bit device id the factory wrote into the FICR
+fn device_id() -> u64 {
+    let ficr = unsafe { &*embassy_nrf::pac::FICR::ptr() };
+    let low = ficr.deviceid[0].read().bits() as u64;
+    let high = ficr.deviceid[1].read().bits() as u64;
+    high << 32 | low
 }
 
 /// Application must run at a lower priority than softdevice
-pub fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
+pub fn enable_softdevice(name: &'static str, address: [u8; 6]) -> &'static mut Softdevice {
     let config = nrf_softdevice::Config {
         clock: Some(raw::nrf_clock_lf_cfg_t {
             source: raw::NRF_CLOCK_LF_SRC_RC as u8,
@@ -87,8 +675,9 @@ pub fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
             accuracy: 7,
         }),
         conn_gap: Some(raw::ble_gap_conn_cfg_t {
-            conn_count: 2,
-            event_length: 24,
+            conn_count: MAX_CONNECTIONS as u8,
+            // 2.5ms each, so every client fits into the 7.5ms interval of `link`
+            event_length: 2,
         }),
         conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 128 }),
         gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
@@ -96,7 +685,7 @@ pub fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
         }),
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: 1,
-            periph_role_count: 3,
+            periph_role_count: MAX_CONNECTIONS as u8,
             // central_role_count: 3,
             // central_sec_count: 0,
             // _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
@@ -115,27 +704,89 @@ pub fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
     let sd = Softdevice::enable(&config);
     set_address(
         sd,
-        &Address::new(
-            nrf_softdevice::ble::AddressType::RandomStatic,
-            [0x13, 0x33, 0x33, 0x33, 0x37, 0b1100_1010],
-        ),
+        &Address::new(nrf_softdevice::ble::AddressType::RandomStatic, address),
     );
     println!("address: {:?}", get_address(&sd));
     sd
 }
 pub static SERVER: StaticCell<Server> = StaticCell::new();
+static NAME: StaticCell<[u8; UNIQUE_NAME_MAX]> = StaticCell::new();
+static FLASH: StaticCell<SharedFlash> = StaticCell::new();
 
 #[embassy_executor::task]
-pub async fn read_ble(s: Spawner, name: &'static str, target_speed: &'static SharedSpeed) {
+pub async fn read_ble(
+    s: Spawner,
+    car_config: &'static CarConfig,
+    target_speed: &'static SharedSpeed,
+    gains: &'static SharedGains,
+    limits: &'static SharedLimits,
+    timeout: &'static SharedTimeout,
+    stored: &'static SharedConfig,
+) {
     // spec for assigned numbers: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Assigned_Numbers/out/en/Assigned_Numbers.pdf?v=1715770644767
-    let mut sd = enable_softdevice("Embassy rcar");
+    let device_id = device_id();
+    let name = rcproto::unique_name(
+        car_config.name(),
+        device_id,
+        NAME.init([0; UNIQUE_NAME_MAX]),
+    );
+    let mut sd = enable_softdevice(name, rcproto::device_address(device_id));
     let server = Server::new(sd).unwrap();
     let server = SERVER.init(server);
+    server
+        .rcar
+        .pid_gains_set(&PidGains::default().encode())
+        .unwrap();
+    server
+        .rcar
+        .motion_limits_set(&MotionLimits::default().encode())
+        .unwrap();
+    server
+        .rcar
+        .failsafe_timeout_ms_set(&rcproto::FAILSAFE_TIMEOUT_MS)
+        .unwrap();
+    server.rcar.config_set(&hidden_pin(car_config)).unwrap();
+    SHOWN.lock(|shown| shown.set(Some(*car_config)));
+    control::set_instructor_pin(car_config.instructor_pin);
+    server
+        .rcar
+        .control_status_set(&control::status(None).encode())
+        .unwrap();
+    server
+        .rcar
+        .link_set(&LinkParams::default().encode())
+        .unwrap();
+    server.rcar.echo_set(&Echo::default().encode()).unwrap();
+    server
+        .rcar
+        .latency_set(&LatencyReport::default().encode())
+        .unwrap();
+    server.rcar.gear_set(&(Gear::default() as u8)).unwrap();
+    server.rcar.compass_set(&(compass::state() as u8)).unwrap();
+    server.rcar.distance_mm_set(&rcproto::NO_OBSTACLE).unwrap();
+    server
+        .rcar
+        .line_follow_set(&LineFollow::default().encode())
+        .unwrap();
+    let flash = FLASH.init(Mutex::new(nrf_softdevice::Flash::take(sd)));
+    bonds::load();
     s.spawn(softdevice_task(sd)).unwrap();
+    s.spawn(settings::store_config(flash, car_config, stored))
+        .unwrap();
+    s.spawn(bonds::store_bonds(flash)).unwrap();
+    s.spawn(report_faults(server)).unwrap();
+    s.spawn(report_pose(server)).unwrap();
+    s.spawn(report_motor_errors(server)).unwrap();
+    s.spawn(report_script(server)).unwrap();
+    s.spawn(report_battery(server)).unwrap();
+    s.spawn(report_distance(server)).unwrap();
+    s.spawn(report_estop(server)).unwrap();
+    s.spawn(report_control(server)).unwrap();
+    s.spawn(report_compass(server)).unwrap();
 
-    static ADV_DATA: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
+    let adv_data: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
         .flags(&[Flag::LE_Only, Flag::GeneralDiscovery])
-        .full_name("rcar")
+        .full_name(name)
         // .raw(
         //     AdvertisementDataType::RANDOM_TARGET_ADDRESS,
         //     &[0xf1, 0x15, 0xba, 0x1e, 0x5e, 0b0000_0011],
@@ -149,7 +800,7 @@ pub async fn read_ble(s: Spawner, name: &'static str, target_speed: &'static Sha
     static SCAN_DATA: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
         .services_128(
             nrf_softdevice::ble::advertisement_builder::ServiceList::Complete,
-            &[0x8a8ec266_3ede_4a2f_a87b_aafbc55b8a30_u128.to_le_bytes()],
+            &[rcproto::SERVICE_UUID.to_le_bytes()],
         )
         .build();
 
@@ -160,19 +811,42 @@ pub async fn read_ble(s: Spawner, name: &'static str, target_speed: &'static Sha
             ..peripheral::Config::default()
         };
         let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
-            adv_data: &ADV_DATA,
+            adv_data: &adv_data,
             scan_data: &SCAN_DATA,
         };
+        control::free_slot().await;
         info!("advertising");
-        let conn = peripheral::advertise_connectable(sd, adv, &config)
+        let conn = peripheral::advertise_pairable(sd, adv, &config, &bonds::BONDER)
             .await
             .unwrap();
 
         defmt::info!("connection established");
-        let mut lock = CONN.lock().await;
-        lock.replace(conn);
+        // bonded clients encrypt again, new ones get to pair
+        if let Err(e) = conn.request_security() {
+            warn!("failed to request security: {}", e);
+        }
+        let Some(slot) = control::connect() else {
+            warn!("no free slot, dropping the connection");
+            if let Err(e) = conn.disconnect() {
+                warn!("failed to disconnect: {}", e);
+            }
+            continue;
+        };
+        info!("client in slot {}", slot);
+        display::set_connected(true);
+        sound::play(Sound::Connected);
+        CONNS.lock().await[slot] = Some(conn.clone());
 
-        if let Err(e) = s.spawn(gatt_server_task(server, target_speed)) {
+        if let Err(e) = s.spawn(gatt_server_task(
+            slot,
+            conn,
+            server,
+            target_speed,
+            gains,
+            limits,
+            timeout,
+            stored,
+        )) {
             defmt::warn!("Error spawning gatt task: {:?}", e);
         }
     }
@@ -0,0 +1,219 @@
+//! Bonds with the clients that paired with the passkey on the LED matrix
+//!
+//! The car only displays, so pairing is passkey entry: the client types in
+//! the six digits scrolling over the matrix, which needs someone standing
+//! next to the car. nrf-softdevice 0.1 has no hook for the DH key of LE
+//! Secure Connections, so [`is_bonded`] takes legacy passkey links as well.
+//!
+//! Bonds are kept in the page below the config, see `memory.x`. With the
+//! table full a new bond pushes out the oldest.
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use embedded_storage_async::nor_flash::NorFlash;
+use heapless::Vec;
+use nrf_softdevice::ble::security::{IoCapabilities, SecurityHandler};
+use nrf_softdevice::ble::{
+    Address, Connection, EncryptionInfo, IdentityKey, IdentityResolutionKey, MasterId, SecurityMode,
+};
+use nrf_softdevice::raw;
+use rcproto::RecordError;
+
+use crate::display;
+use crate::settings::{SharedFlash, PAGE_SIZE};
+
+/// start of the page reserved in `memory.x`
+const BONDS_PAGE: u32 = 0x7E000;
+const MAX_BONDS: usize = 4;
+const MAGIC: [u8; 4] = *b"RBND";
+/// ltk, key flags, ediv, rand, address type, address and irk
+const BOND_LEN: usize = 16 + 1 + 2 + 8 + 1 + 6 + 16;
+/// count and bonds
+const BODY_LEN: usize = 1 + MAX_BONDS * BOND_LEN;
+const RECORD_LEN: usize = rcproto::record_len(BODY_LEN);
+
+#[derive(Clone, Copy)]
+struct Bond {
+    master_id: MasterId,
+    key: EncryptionInfo,
+    peer_id: IdentityKey,
+}
+
+impl Bond {
+    fn encode(&self) -> [u8; BOND_LEN] {
+        let mut buf = [0; BOND_LEN];
+        buf[0..16].copy_from_slice(&self.key.ltk);
+        buf[16] = self.key.flags;
+        buf[17..19].copy_from_slice(&self.master_id.ediv.to_le_bytes());
+        buf[19..27].copy_from_slice(&self.master_id.rand);
+        buf[27] = self.peer_id.addr.address_type() as u8;
+        buf[28..34].copy_from_slice(&self.peer_id.addr.bytes());
+        buf[34..50].copy_from_slice(&self.peer_id.irk.as_raw().irk);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Bond> {
+        let addr = Address::new(
+            rcproto::address_type!(buf[27])?,
+            buf[28..34].try_into().ok()?,
+        );
+        let irk = raw::ble_gap_irk_t {
+            irk: buf[34..50].try_into().ok()?,
+        };
+        Some(Bond {
+            master_id: MasterId {
+                ediv: u16::from_le_bytes([buf[17], buf[18]]),
+                rand: buf[19..27].try_into().ok()?,
+            },
+            key: EncryptionInfo {
+                ltk: buf[0..16].try_into().ok()?,
+                flags: buf[16],
+            },
+            peer_id: IdentityKey {
+                irk: IdentityResolutionKey::from_raw(irk),
+                addr,
+            },
+        })
+    }
+}
+
+fn encode_record(bonds: &[Bond]) -> [u8; RECORD_LEN] {
+    let mut body = [0; BODY_LEN];
+    body[0] = bonds.len() as u8;
+    for (chunk, bond) in body[1..].chunks_mut(BOND_LEN).zip(bonds) {
+        chunk.copy_from_slice(&bond.encode());
+    }
+    rcproto::frame(MAGIC, &body)
+}
+
+fn decode_record(record: &[u8]) -> Option<Vec<Bond, MAX_BONDS>> {
+    let body = match rcproto::unframe(MAGIC, record, BODY_LEN) {
+        Ok(body) => body,
+        Err(RecordError::Missing) => return None,
+        Err(RecordError::Checksum) => {
+            warn!("bonds checksum mismatch");
+            return None;
+        }
+    };
+    let count = (body[0] as usize).min(MAX_BONDS);
+    body[1..]
+        .chunks(BOND_LEN)
+        .take(count)
+        .map(Bond::decode)
+        .collect()
+}
+
+static BONDS: Mutex<ThreadModeRawMutex, RefCell<Vec<Bond, MAX_BONDS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+/// wakes [`store_bonds`]
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// the security handler of every connection
+pub struct Bonder;
+
+pub static BONDER: Bonder = Bonder;
+
+impl SecurityHandler for Bonder {
+    fn io_capabilities(&self) -> IoCapabilities {
+        IoCapabilities::DisplayOnly
+    }
+
+    fn can_bond(&self, _conn: &Connection) -> bool {
+        true
+    }
+
+    fn display_passkey(&self, passkey: &[u8; 6]) {
+        info!("pairing, passkey {=[u8]:a}", passkey);
+        display::show_passkey(Some(*passkey));
+    }
+
+    fn on_security_update(&self, _conn: &Connection, security_mode: SecurityMode) {
+        info!("link security: {}", security_mode);
+        display::show_passkey(None);
+    }
+
+    fn on_bonded(
+        &self,
+        conn: &Connection,
+        master_id: MasterId,
+        key: EncryptionInfo,
+        peer_id: IdentityKey,
+    ) {
+        info!("bonded with {}", peer_id.addr);
+        let bond = Bond {
+            master_id,
+            key,
+            peer_id,
+        };
+        BONDS.lock(|bonds| {
+            let mut bonds = bonds.borrow_mut();
+            bonds.retain(|b| !b.peer_id.is_match(conn.peer_address()));
+            if bonds.is_full() {
+                warn!("bond table full, forgetting the oldest");
+                bonds.remove(0);
+            }
+            let _ = bonds.push(bond);
+        });
+        CHANGED.signal(());
+    }
+
+    fn get_key(&self, _conn: &Connection, master_id: MasterId) -> Option<EncryptionInfo> {
+        BONDS.lock(|bonds| {
+            let bonds = bonds.borrow();
+            bonds
+                .iter()
+                .find(|b| b.master_id == master_id)
+                .map(|b| b.key)
+        })
+    }
+}
+
+/// whether `conn` is encrypted after a passkey pairing with a bonded peer
+pub fn is_bonded(conn: &Connection) -> bool {
+    let authenticated = matches!(
+        conn.security_mode(),
+        SecurityMode::Mitm | SecurityMode::LescMitm
+    );
+    authenticated
+        && BONDS.lock(|bonds| {
+            let bonds = bonds.borrow();
+            bonds
+                .iter()
+                .any(|b| b.peer_id.is_match(conn.peer_address()))
+        })
+}
+
+/// reads the stored bonds, to be called before advertising
+pub fn load() {
+    let record = unsafe { rcproto::read_flash(BONDS_PAGE, RECORD_LEN) };
+    match decode_record(record) {
+        Some(stored) => {
+            info!("loaded {} bonds", stored.len());
+            BONDS.lock(|bonds| bonds.replace(stored));
+        }
+        None => info!("no stored bonds"),
+    }
+}
+
+/// writes the bond table to flash whenever a client bonds
+#[embassy_executor::task]
+pub async fn store_bonds(flash: &'static SharedFlash) {
+    loop {
+        CHANGED.wait().await;
+        let record = BONDS.lock(|bonds| encode_record(&bonds.borrow()));
+        let mut flash = flash.lock().await;
+        let res = match flash.erase(BONDS_PAGE, BONDS_PAGE + PAGE_SIZE).await {
+            Ok(()) => flash.write(BONDS_PAGE, &record).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => info!("stored bonds"),
+            Err(e) => warn!("failed to store bonds: {}", e),
+        }
+    }
+}
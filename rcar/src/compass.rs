@@ -0,0 +1,179 @@
+//! Heading from the LSM303AGR magnetometer on the micro:bit v2
+//!
+//! The magnetometer sits on the internal i2c bus. The heading is published
+//! relative to the last zeroing, the first reading counts as zero.
+//!
+//! There is no heading until the compass is calibrated: asked to, the motor
+//! task spins the car in place while the readings go into a
+//! [`HardIronCapture`], and the offset it finds is kept in the config.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{info, warn};
+use embassy_nrf::{
+    bind_interrupts,
+    interrupt::{self, InterruptExt},
+    peripherals::{self, P0_08, P0_16, TWISPI0},
+    twim::{self, Twim},
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker};
+use rcdrive::heading::{mag_heading, wrap, HardIron, HardIronCapture};
+use rcproto::{CompassState, VelocityCommand};
+
+use crate::{settings, SharedHeading};
+
+bind_interrupts!(struct Irqs {
+    SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0 => twim::InterruptHandler<peripherals::TWISPI0>;
+});
+
+const MAG_ADDRESS: u8 = 0x1E;
+const CFG_REG_A_M: u8 = 0x60;
+const CFG_REG_C_M: u8 = 0x62;
+const OUTX_L_REG_M: u8 = 0x68;
+
+/// turn rate while calibrating, as a share of full speed
+const SPIN: f32 = 0.4;
+/// well over a full turn at [`SPIN`]
+const CALIBRATION_TIME: Duration = Duration::from_secs(8);
+/// least spread a full turn gives in the earth's field, in LSB of 1.5mG
+const MIN_SPAN: f32 = 100.0;
+
+/// makes the current heading the new zero
+pub static ZERO_HEADING: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// signalled whenever the state of the calibration changes
+pub static STATE: Signal<ThreadModeRawMutex, CompassState> = Signal::new();
+
+/// whether the magnetometer answered at boot
+static FOUND: AtomicBool = AtomicBool::new(false);
+static CALIBRATING: AtomicBool = AtomicBool::new(false);
+static CALIBRATED: AtomicBool = AtomicBool::new(false);
+
+pub fn state() -> CompassState {
+    if CALIBRATING.load(Ordering::Relaxed) {
+        CompassState::Calibrating
+    } else if CALIBRATED.load(Ordering::Relaxed) {
+        CompassState::Calibrated
+    } else {
+        CompassState::Uncalibrated
+    }
+}
+
+fn set_calibrating(calibrating: bool) {
+    if CALIBRATING.swap(calibrating, Ordering::Relaxed) != calibrating {
+        STATE.signal(state());
+    }
+}
+
+fn set_calibrated(hard_iron: Option<HardIron>) {
+    CALIBRATED.store(hard_iron.is_some(), Ordering::Relaxed);
+    STATE.signal(state());
+}
+
+/// whether the heading can be trusted for headless driving
+pub fn calibrated() -> bool {
+    CALIBRATED.load(Ordering::Relaxed)
+}
+
+/// starts spinning the car to calibrate the compass
+pub fn calibrate() {
+    if !FOUND.load(Ordering::Relaxed) {
+        warn!("no magnetometer to calibrate");
+        return;
+    }
+    info!("calibrating the compass");
+    set_calibrating(true);
+}
+
+/// stops a running calibration, e.g. when the failsafe trips
+pub fn abort() {
+    if CALIBRATING.load(Ordering::Relaxed) {
+        warn!("compass calibration aborted");
+        set_calibrating(false);
+    }
+}
+
+/// the spin while calibrating, the motor task drives it instead of the joystick
+pub fn tick() -> Option<VelocityCommand> {
+    CALIBRATING
+        .load(Ordering::Relaxed)
+        .then(|| VelocityCommand::new(0.0, 0.0, SPIN))
+}
+
+#[embassy_executor::task]
+pub async fn read_heading(
+    twi0: TWISPI0,
+    scl: P0_08,
+    sda: P0_16,
+    heading: &'static SharedHeading,
+    offset: Option<[f32; 2]>,
+) {
+    let mut i2c_config = twim::Config::default();
+    i2c_config.frequency = twim::Frequency::K400;
+    interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0.set_priority(interrupt::Priority::P5);
+    let mut twim = Twim::new(twi0, Irqs, sda, scl, i2c_config);
+
+    // temperature compensated, 100 Hz, continuous; block data update
+    for cmd in [[CFG_REG_A_M, 0x8C], [CFG_REG_C_M, 0x10]] {
+        if let Err(e) = twim.write(MAG_ADDRESS, &cmd).await {
+            warn!("magnetometer not responding, no headless driving: {}", e);
+            return;
+        }
+    }
+
+    FOUND.store(true, Ordering::Relaxed);
+    let mut hard_iron = offset.map(|offset| HardIron { offset });
+    set_calibrated(hard_iron);
+    let mut capture: Option<(HardIronCapture, Instant)> = None;
+    let mut zero = None;
+    let mut ticker = Ticker::every(Duration::from_millis(20));
+    loop {
+        ticker.next().await;
+        let mut raw = [0; 6];
+        if let Err(e) = twim
+            .write_read(MAG_ADDRESS, &[OUTX_L_REG_M], &mut raw)
+            .await
+        {
+            warn!("failed to read magnetometer: {}", e);
+            continue;
+        }
+        let x = i16::from_le_bytes([raw[0], raw[1]]) as f32;
+        let y = i16::from_le_bytes([raw[2], raw[3]]) as f32;
+
+        if !CALIBRATING.load(Ordering::Relaxed) {
+            capture = None;
+        } else if capture.is_none() {
+            capture = Some((HardIronCapture::default(), Instant::now()));
+        }
+        if let Some((readings, started)) = &mut capture {
+            readings.add(x, y);
+            if started.elapsed() < CALIBRATION_TIME {
+                continue;
+            }
+            match readings.finish(MIN_SPAN) {
+                Some(found) => {
+                    info!("compass calibrated: {}", found);
+                    hard_iron = Some(found);
+                    settings::HARD_IRON.signal(found.offset);
+                    zero = None;
+                }
+                None => warn!("compass calibration failed, the car didn't turn all the way"),
+            }
+            capture = None;
+            CALIBRATING.store(false, Ordering::Relaxed);
+            set_calibrated(hard_iron);
+            continue;
+        }
+        let Some(correction) = hard_iron else {
+            continue;
+        };
+        let (x, y) = correction.correct(x, y);
+        let yaw = mag_heading(x, y);
+
+        if ZERO_HEADING.try_take().is_some() || zero.is_none() {
+            info!("heading zeroed");
+            zero = Some(yaw);
+        }
+        *heading.lock().await = wrap(yaw - zero.unwrap_or(yaw));
+    }
+}
@@ -0,0 +1,96 @@
+//! Which connected client drives the car, see `rcdrive::arbiter`
+//!
+//! The gatt server keeps one connection per slot, the slots and the driver
+//! are kept here. Anybody changing the table signals [`CHANGED`], so every
+//! client hears about it.
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use rcdrive::arbiter::{Arbiter, ControlError};
+use rcproto::{ControlRequest, ControlStatus};
+
+/// as many as the softdevice takes, see `periph_role_count`
+pub const MAX_CONNECTIONS: usize = 3;
+
+static ARBITER: Mutex<ThreadModeRawMutex, RefCell<Arbiter<MAX_CONNECTIONS>>> =
+    Mutex::new(RefCell::new(Arbiter::new()));
+
+/// signalled whenever a client connects, leaves or the driver changes
+pub static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// wakes the advertising loop when a slot frees up
+static FREED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// 0 turns the instructor override off
+pub fn set_instructor_pin(pin: u32) {
+    ARBITER.lock(|a| a.borrow_mut().instructor_pin = pin);
+}
+
+pub fn instructor_pin() -> u32 {
+    ARBITER.lock(|a| a.borrow().instructor_pin)
+}
+
+/// waits until another client may connect
+pub async fn free_slot() {
+    while ARBITER.lock(|a| a.borrow().is_full()) {
+        FREED.wait().await;
+    }
+}
+
+/// the slot of a new connection, `None` with every slot taken
+pub fn connect() -> Option<usize> {
+    let slot = ARBITER.lock(|a| a.borrow_mut().connect());
+    CHANGED.signal(());
+    slot
+}
+
+/// frees `slot`, returns whether the client was the driver
+pub fn disconnect(slot: usize) -> bool {
+    let was_driver = ARBITER.lock(|a| a.borrow_mut().disconnect(slot));
+    if was_driver {
+        warn!("driver in slot {} left", slot);
+    }
+    CHANGED.signal(());
+    FREED.signal(());
+    was_driver
+}
+
+pub fn may_drive(slot: usize) -> bool {
+    ARBITER.lock(|a| a.borrow().may_drive(slot))
+}
+
+pub fn may_set_pin(slot: usize) -> bool {
+    ARBITER.lock(|a| a.borrow().may_set_pin(slot))
+}
+
+/// carries out `request` of the client in `slot`, returns whether the
+/// driver changed
+pub fn apply(slot: usize, request: &ControlRequest) -> Result<bool, ControlError> {
+    let (before, after) = ARBITER.lock(|a| {
+        let mut a = a.borrow_mut();
+        let before = a.driver();
+        a.apply(slot, request)?;
+        Ok((before, a.driver()))
+    })?;
+    if before == after {
+        return Ok(false);
+    }
+    match after {
+        Some(driver) => info!(
+            "slot {} drives, after {} from slot {}",
+            driver, request, slot
+        ),
+        None => info!("nobody drives, slot {} released control", slot),
+    }
+    CHANGED.signal(());
+    Ok(true)
+}
+
+/// the table as the client in `you` sees it
+pub fn status(you: Option<usize>) -> ControlStatus {
+    ARBITER.lock(|a| a.borrow().status(you))
+}
@@ -0,0 +1,205 @@
+//! The 5x5 LED matrix of the car
+//!
+//! Shows the passkey digit by digit while a client pairs. Blinks the center
+//! while advertising, a cross during an emergency stop and an exclamation
+//! mark while a fault is latched. Once connected it points where the car is
+//! told to drive. A new gear shows up as one to three bars, and every few
+//! seconds the battery level as a bar graph.
+//!
+//! The matrix is multiplexed, so it is only lit while [`show_status`] keeps
+//! rendering it.
+
+use core::cell::Cell;
+use core::f32::consts::PI;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::select;
+use embassy_nrf::gpio::{AnyPin, Level, Output, OutputDrive};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer};
+use microbit_bsp::display::{fonts::frame_5x5, Frame, LedMatrix};
+use micromath::F32Ext;
+use rcproto::{Gear, VelocityCommand};
+
+use crate::{battery, estop, failsafe::FAULTS};
+
+pub type Matrix = LedMatrix<Output<'static, AnyPin>, 5, 5>;
+
+/// one row at a time, fast enough not to flicker
+const ROW_PERIOD: Duration = Duration::from_micros(500);
+/// how often the picture is updated
+const FRAME_PERIOD: Duration = Duration::from_millis(100);
+/// frames per on or off phase of blinking
+const BLINK_FRAMES: u32 = 5;
+/// frames the gear or the battery level stay up
+const OVERLAY_FRAMES: u32 = 10;
+/// frames between showing the battery level
+const BATTERY_EVERY: u32 = 100;
+/// slower commands count as standing still
+const STILL: f32 = 0.05;
+/// frames a passkey digit is lit, followed by a blank one
+const DIGIT_FRAMES: u32 = 8;
+/// frames for the whole passkey, with a pause before it starts over
+const PASSKEY_FRAMES: u32 = 6 * DIGIT_FRAMES + 10;
+
+/// the gear the motor task drives with
+pub static GEAR: Signal<ThreadModeRawMutex, Gear> = Signal::new();
+/// the command the motor task drives with
+pub static COMMAND: Signal<ThreadModeRawMutex, VelocityCommand> = Signal::new();
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+/// ascii digits of the passkey while a client pairs
+static PASSKEY: Mutex<ThreadModeRawMutex, Cell<Option<[u8; 6]>>> = Mutex::new(Cell::new(None));
+
+/// to be called when a client connects or the connection closes
+pub fn set_connected(connected: bool) {
+    CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+/// shows `passkey` over everything else until it is taken down with `None`
+pub fn show_passkey(passkey: Option<[u8; 6]>) {
+    PASSKEY.lock(|p| p.set(passkey));
+}
+
+/// the matrix on its row (anode) and column (cathode) pins, all off
+pub fn matrix(rows: [AnyPin; 5], cols: [AnyPin; 5]) -> Matrix {
+    LedMatrix::new(
+        rows.map(|pin| Output::new(pin, Level::Low, OutputDrive::Standard)),
+        cols.map(|pin| Output::new(pin, Level::High, OutputDrive::Standard)),
+    )
+}
+
+const BLANK: Frame<5, 5> = frame_5x5(&[0; 5]);
+const DOT: Frame<5, 5> = frame_5x5(&[0b00000, 0b00000, 0b00100, 0b00000, 0b00000]);
+const STOPPED: Frame<5, 5> = frame_5x5(&[0b10001, 0b01010, 0b00100, 0b01010, 0b10001]);
+const FAULT: Frame<5, 5> = frame_5x5(&[0b00100, 0b00100, 0b00100, 0b00000, 0b00100]);
+/// counterclockwise from pointing right
+const ARROWS: [Frame<5, 5>; 8] = [
+    frame_5x5(&[0b00100, 0b00010, 0b11111, 0b00010, 0b00100]),
+    frame_5x5(&[0b00111, 0b00011, 0b00101, 0b01000, 0b10000]),
+    frame_5x5(&[0b00100, 0b01110, 0b10101, 0b00100, 0b00100]),
+    frame_5x5(&[0b11100, 0b11000, 0b10100, 0b00010, 0b00001]),
+    frame_5x5(&[0b00100, 0b01000, 0b11111, 0b01000, 0b00100]),
+    frame_5x5(&[0b00001, 0b00010, 0b10100, 0b11000, 0b11100]),
+    frame_5x5(&[0b00100, 0b00100, 0b10101, 0b01110, 0b00100]),
+    frame_5x5(&[0b10000, 0b01000, 0b00101, 0b00011, 0b00111]),
+];
+
+const DIGITS: [Frame<5, 5>; 10] = [
+    frame_5x5(&[0b01110, 0b01010, 0b01010, 0b01010, 0b01110]),
+    frame_5x5(&[0b00100, 0b01100, 0b00100, 0b00100, 0b01110]),
+    frame_5x5(&[0b01110, 0b00010, 0b01110, 0b01000, 0b01110]),
+    frame_5x5(&[0b01110, 0b00010, 0b00110, 0b00010, 0b01110]),
+    frame_5x5(&[0b01010, 0b01010, 0b01110, 0b00010, 0b00010]),
+    frame_5x5(&[0b01110, 0b01000, 0b01110, 0b00010, 0b01110]),
+    frame_5x5(&[0b01110, 0b01000, 0b01110, 0b01010, 0b01110]),
+    frame_5x5(&[0b01110, 0b00010, 0b00100, 0b00100, 0b00100]),
+    frame_5x5(&[0b01110, 0b01010, 0b01110, 0b01010, 0b01110]),
+    frame_5x5(&[0b01110, 0b01010, 0b01110, 0b00010, 0b01110]),
+];
+
+/// one digit after the other, blank in between so repeats stand out
+fn passkey_frame(frame: u32, passkey: &[u8; 6]) -> Frame<5, 5> {
+    let step = frame % PASSKEY_FRAMES;
+    let digit = passkey
+        .get((step / DIGIT_FRAMES) as usize)
+        .and_then(|d| DIGITS.get(d.wrapping_sub(b'0') as usize));
+    match digit {
+        Some(digit) if step % DIGIT_FRAMES < DIGIT_FRAMES - 1 => *digit,
+        _ => BLANK,
+    }
+}
+
+fn gear_frame(gear: Gear) -> Frame<5, 5> {
+    match gear {
+        Gear::Crawl => frame_5x5(&[0b00000, 0b00000, 0b00000, 0b10000, 0b10000]),
+        Gear::Normal => frame_5x5(&[0b00000, 0b00000, 0b00100, 0b10100, 0b10100]),
+        Gear::Turbo => frame_5x5(&[0b00001, 0b00001, 0b00101, 0b10101, 0b10101]),
+    }
+}
+
+/// a full row per started 20%, from the bottom up
+fn battery_frame(level: u8) -> Frame<5, 5> {
+    let rows = (level as usize).div_ceil(20);
+    frame_5x5(&core::array::from_fn(|i| {
+        if i >= 5 - rows {
+            0b11111
+        } else {
+            0
+        }
+    }))
+}
+
+/// the arrow closest to the direction of `x` and `y`, the dot when still
+fn direction_frame(command: &VelocityCommand) -> Frame<5, 5> {
+    let (x, y) = (command.x, command.y);
+    if x.abs() < STILL && y.abs() < STILL {
+        return DOT;
+    }
+    let sector = (F32Ext::atan2(y, x) / (PI / 4.0)).round() as i32;
+    ARROWS[sector.rem_euclid(8) as usize]
+}
+
+fn status_frame(frame: u32, command: &VelocityCommand) -> Frame<5, 5> {
+    let blink_on = frame / BLINK_FRAMES % 2 == 0;
+    if estop::stopped() {
+        if blink_on {
+            STOPPED
+        } else {
+            BLANK
+        }
+    } else if !CONNECTED.load(Ordering::Relaxed) {
+        if blink_on {
+            DOT
+        } else {
+            BLANK
+        }
+    } else if !FAULTS.get().is_empty() {
+        if blink_on {
+            FAULT
+        } else {
+            BLANK
+        }
+    } else {
+        direction_frame(command)
+    }
+}
+
+async fn refresh(matrix: &mut Matrix) {
+    loop {
+        matrix.render();
+        Timer::after(ROW_PERIOD).await;
+    }
+}
+
+#[embassy_executor::task]
+pub async fn show_status(mut matrix: Matrix) {
+    let mut frame: u32 = 0;
+    let mut command = VelocityCommand::default();
+    // a picture shown over the status until the given frame
+    let mut overlay = None;
+    loop {
+        select(Timer::after(FRAME_PERIOD), refresh(&mut matrix)).await;
+        frame += 1;
+
+        if let Some(c) = COMMAND.try_take() {
+            command = c;
+        }
+        if let Some(gear) = GEAR.try_take() {
+            overlay = Some((gear_frame(gear), frame + OVERLAY_FRAMES));
+        } else if frame % BATTERY_EVERY == 0 {
+            if let Some(level) = battery::level() {
+                overlay = Some((battery_frame(level), frame + OVERLAY_FRAMES));
+            }
+        }
+        let picture = match (PASSKEY.lock(|p| p.get()), overlay) {
+            (Some(passkey), _) => passkey_frame(frame, &passkey),
+            (None, Some((picture, until))) if frame < until => picture,
+            _ => status_frame(frame, &command),
+        };
+        matrix.apply(picture);
+    }
+}
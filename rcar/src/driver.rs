@@ -0,0 +1,219 @@
+//! The motor driver selected in the car config, on the pins of this car
+//!
+//! Both i2c boards hang off the edge connector i2c bus. The h-bridges take
+//! ring pins 0 and 1 and pins 8 and 12 to 16, which clash with the encoders.
+//! Ring pin 2 is left to measure the battery.
+//!
+//! The i2c boards keep their pins, so a wedged bus can be clocked free and
+//! the `Twim` set up again, see [`I2cBoard::recover`].
+
+use core::convert::Infallible;
+
+use defmt::{info, warn};
+use embassy_nrf::{
+    bind_interrupts,
+    gpio::{AnyPin, Flex, Input, OutputDrive, Pull},
+    interrupt::{self, InterruptExt},
+    peripherals::{self, P0_26, P1_00, PWM0, PWM1, TWISPI1},
+    pwm::SimplePwm,
+    twim::{self, Twim},
+    Peripheral,
+};
+use embassy_time::{with_timeout, Duration, Timer};
+use rcdrive::driver::{DriverError, HBridge, MotorDriver, Pca9685, Pwm, Wukong};
+use rcproto::MotorDriverKind;
+
+bind_interrupts!(struct Irqs {
+    SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1 => twim::InterruptHandler<peripherals::TWISPI1>;
+});
+
+/// 16kHz at the default 16MHz pwm clock, above what the motors whine at
+const MAX_DUTY: u16 = 1000;
+/// a 4 byte frame takes well under 1ms at 100kHz
+const I2C_TIMEOUT: Duration = Duration::from_millis(5);
+
+type I2cBus = Twim<'static, TWISPI1>;
+
+/// the i2c peripheral and pins of the edge connector bus
+pub struct I2cPins {
+    pub twi: TWISPI1,
+    pub scl: P0_26,
+    pub sda: P1_00,
+}
+
+pub struct I2cBoard {
+    kind: MotorDriverKind,
+    address: u8,
+    pins: I2cPins,
+    /// `None` only while recovering
+    twim: Option<I2cBus>,
+}
+
+impl I2cBoard {
+    fn twim(pins: &I2cPins) -> I2cBus {
+        let mut i2c_config = twim::Config::default();
+        i2c_config.frequency = twim::Frequency::K100;
+        i2c_config.sda_pullup = true;
+        i2c_config.scl_pullup = true;
+        i2c_config.sda_high_drive = true;
+        i2c_config.scl_high_drive = true;
+
+        interrupt::SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1.set_priority(interrupt::Priority::P5);
+        // safe as long as there is only ever one twim, see `recover`
+        let (twi, scl, sda) = unsafe {
+            (
+                pins.twi.clone_unchecked(),
+                pins.scl.clone_unchecked(),
+                pins.sda.clone_unchecked(),
+            )
+        };
+        Twim::new(twi, Irqs, sda, scl, i2c_config)
+    }
+
+    fn bus(&mut self) -> &mut I2cBus {
+        self.twim
+            .as_mut()
+            .expect("twim is only taken while recovering")
+    }
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Error> {
+        let (kind, address) = (self.kind, self.address);
+        let twim = self.bus();
+        let write = async {
+            match kind {
+                MotorDriverKind::Pca9685 => Pca9685::new(twim, address).set(channel, output).await,
+                _ => Wukong::new(twim, address).set(channel, output).await,
+            }
+        };
+        with_timeout(I2C_TIMEOUT, write)
+            .await
+            .unwrap_or(Err(DriverError::Timeout))
+    }
+
+    async fn init(&mut self) -> Result<(), Error> {
+        let address = self.address;
+        match self.kind {
+            MotorDriverKind::Pca9685 => Pca9685::new(self.bus(), address).init().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// clocks out whatever a board is still sending, then sets the twim up again
+    async fn recover(&mut self) -> Result<(), Error> {
+        warn!("recovering i2c bus");
+        // the old twim has to release the pins first
+        self.twim = None;
+        {
+            let sda = Input::new(&mut self.pins.sda, Pull::Up);
+            let mut scl = Flex::new(&mut self.pins.scl);
+            scl.set_high();
+            scl.set_as_input_output(Pull::Up, OutputDrive::Standard0Disconnect1);
+            // a board holding sda low lets go within 9 clocks
+            for _ in 0..9 {
+                if sda.is_high() {
+                    break;
+                }
+                scl.set_low();
+                Timer::after_micros(5).await;
+                scl.set_high();
+                Timer::after_micros(5).await;
+            }
+        }
+        self.twim = Some(Self::twim(&self.pins));
+        // the board may have been reset as well
+        self.init().await
+    }
+}
+
+/// one of the two pwm peripherals driving the h-bridges
+pub enum NrfPwm {
+    Pwm0(SimplePwm<'static, PWM0>),
+    Pwm1(SimplePwm<'static, PWM1>),
+}
+
+impl Pwm for NrfPwm {
+    fn max_duty(&self) -> u16 {
+        match self {
+            NrfPwm::Pwm0(pwm) => pwm.max_duty(),
+            NrfPwm::Pwm1(pwm) => pwm.max_duty(),
+        }
+    }
+
+    fn set_duty(&mut self, output: usize, duty: u16) {
+        match self {
+            NrfPwm::Pwm0(pwm) => pwm.set_duty(output, duty),
+            NrfPwm::Pwm1(pwm) => pwm.set_duty(output, duty),
+        }
+    }
+}
+
+pub enum Driver {
+    I2c(I2cBoard),
+    HBridge(HBridge<NrfPwm>),
+}
+
+pub type Error = DriverError<twim::Error>;
+
+/// the h-bridges never fail on a bus
+fn widen(e: DriverError<Infallible>) -> Error {
+    match e {
+        DriverError::Bus(never) => match never {},
+        DriverError::Channel(c) => DriverError::Channel(c),
+        DriverError::Timeout => DriverError::Timeout,
+    }
+}
+
+impl Driver {
+    /// a board on the i2c bus at `address`
+    pub fn i2c(kind: MotorDriverKind, address: u8, pins: I2cPins) -> Self {
+        info!("Initializing TWI...");
+        let kind = match kind {
+            MotorDriverKind::HBridge => {
+                warn!("no h-bridge in this build, falling back to the wukong board");
+                MotorDriverKind::Wukong
+            }
+            kind => kind,
+        };
+        Driver::I2c(I2cBoard {
+            kind,
+            address,
+            twim: Some(I2cBoard::twim(&pins)),
+            pins,
+        })
+    }
+
+    /// h-bridges with the inputs of channel 0 to 3 on `pins`, in pairs
+    pub fn hbridge(pwm0: PWM0, pwm1: PWM1, pins: [AnyPin; 8]) -> Self {
+        let [a0, a1, a2, a3, b0, b1, b2, b3] = pins;
+        let pwm0 = SimplePwm::new_4ch(pwm0, a0, a1, a2, a3);
+        let pwm1 = SimplePwm::new_4ch(pwm1, b0, b1, b2, b3);
+        pwm0.set_max_duty(MAX_DUTY);
+        pwm1.set_max_duty(MAX_DUTY);
+        Driver::HBridge(HBridge::new([NrfPwm::Pwm0(pwm0), NrfPwm::Pwm1(pwm1)]))
+    }
+}
+
+impl MotorDriver for Driver {
+    type Error = Error;
+
+    async fn init(&mut self) -> Result<(), Error> {
+        match self {
+            Driver::I2c(d) => d.init().await,
+            Driver::HBridge(d) => d.init().await.map_err(widen),
+        }
+    }
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Error> {
+        match self {
+            Driver::I2c(d) => d.set(channel, output).await,
+            Driver::HBridge(d) => d.set(channel, output).await.map_err(widen),
+        }
+    }
+
+    async fn recover(&mut self) -> Result<(), Error> {
+        match self {
+            Driver::I2c(d) => d.recover().await,
+            Driver::HBridge(d) => d.recover().await.map_err(widen),
+        }
+    }
+}
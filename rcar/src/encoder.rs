@@ -0,0 +1,120 @@
+//! Optional wheel encoders, counted with GPIOTE or the QDEC peripheral
+//!
+//! Every counting task registers its wheel in [`ENCODERS`], the speed loop in
+//! [`crate::motor`] only closes the loop for wheels that have one. Which kind
+//! is fitted comes from `CarConfig::encoders`, the pins only with the
+//! `encoders` feature.
+
+use core::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+
+use defmt::{info, Format};
+use embassy_nrf::{
+    bind_interrupts,
+    gpio::{AnyPin, Input},
+    interrupt::{self, InterruptExt},
+    peripherals::{self, QDEC},
+    qdec::{self, Qdec},
+};
+use rcproto::{CarConfig, EncoderKind};
+
+bind_interrupts!(struct Irqs {
+    QDEC => qdec::InterruptHandler<peripherals::QDEC>;
+});
+
+type Pin = Input<'static, AnyPin>;
+
+/// index of a wheel, in the order of `WheelSpeed::to_array`
+#[derive(Clone, Copy, Debug, Format)]
+pub enum Wheel {
+    FrontLeft = 0,
+    BackLeft = 1,
+    FrontRight = 2,
+    BackRight = 3,
+}
+
+pub struct Encoders {
+    counts: [AtomicI32; 4],
+    kinds: [AtomicU8; 4],
+}
+
+pub static ENCODERS: Encoders = Encoders::new();
+
+impl Encoders {
+    const fn new() -> Self {
+        Encoders {
+            counts: [
+                AtomicI32::new(0),
+                AtomicI32::new(0),
+                AtomicI32::new(0),
+                AtomicI32::new(0),
+            ],
+            kinds: [
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+            ],
+        }
+    }
+
+    fn attach(&self, wheel: Wheel, kind: EncoderKind) {
+        info!("{} encoder on {}", kind, wheel);
+        self.kinds[wheel as usize].store(kind as u8, Ordering::Relaxed);
+    }
+
+    fn add(&self, wheel: Wheel, steps: i32) {
+        self.counts[wheel as usize].fetch_add(steps, Ordering::Relaxed);
+    }
+
+    /// what is counting each wheel, see `rcdrive::SpeedMeter`
+    pub fn kinds(&self) -> [EncoderKind; 4] {
+        core::array::from_fn(|i| {
+            self.kinds[i]
+                .load(Ordering::Relaxed)
+                .try_into()
+                .unwrap_or(EncoderKind::None)
+        })
+    }
+
+    pub fn counts(&self) -> [i32; 4] {
+        core::array::from_fn(|i| self.counts[i].load(Ordering::Relaxed))
+    }
+}
+
+/// whether this build has pins for the encoders in `config`
+pub fn fits(config: &CarConfig) -> bool {
+    cfg!(feature = "encoders") || config.encoders == EncoderKind::None
+}
+
+/// quadrature decoded in software, on both edges of channel a
+#[embassy_executor::task(pool_size = 4)]
+pub async fn count_quadrature(wheel: Wheel, mut a: Pin, b: Pin) {
+    ENCODERS.attach(wheel, EncoderKind::Quadrature);
+    loop {
+        a.wait_for_any_edge().await;
+        let step = if a.is_high() == b.is_high() { -1 } else { 1 };
+        ENCODERS.add(wheel, step);
+    }
+}
+
+/// pulses of a single channel, on both edges
+#[embassy_executor::task(pool_size = 4)]
+pub async fn count_hall(wheel: Wheel, mut pulse: Pin) {
+    ENCODERS.attach(wheel, EncoderKind::Hall);
+    loop {
+        pulse.wait_for_any_edge().await;
+        ENCODERS.add(wheel, 1);
+    }
+}
+
+/// quadrature decoded by the QDEC peripheral, there is only one of those
+#[embassy_executor::task]
+pub async fn count_qdec(wheel: Wheel, qdec: QDEC, a: AnyPin, b: AnyPin) {
+    interrupt::QDEC.set_priority(interrupt::Priority::P5);
+    let mut qdec = Qdec::new(qdec, Irqs, a, b, qdec::Config::default());
+    ENCODERS.attach(wheel, EncoderKind::Quadrature);
+    loop {
+        let delta = qdec.read().await;
+        ENCODERS.add(wheel, delta as i32);
+    }
+}
@@ -0,0 +1,63 @@
+//! Emergency stop from button A or BLE, see `rcdrive::estop`
+//!
+//! A trigger wakes the motor task at once, which then writes neutral frames
+//! without ramping down and keeps them until the stop is re-armed.
+
+use core::cell::RefCell;
+
+use defmt::{error, info, warn};
+use embassy_nrf::gpio::{AnyPin, Input};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use rcdrive::estop::EStopLatch;
+use rcproto::{EStopState, VelocityCommand};
+
+static LATCH: Mutex<ThreadModeRawMutex, RefCell<EStopLatch>> =
+    Mutex::new(RefCell::new(EStopLatch::new()));
+
+/// wakes the motor task
+static TRIGGERED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// signalled whenever the state changes
+pub static STATE: Signal<ThreadModeRawMutex, EStopState> = Signal::new();
+
+pub fn trigger(source: &'static str) {
+    if LATCH.lock(|latch| latch.borrow_mut().trigger()) {
+        error!("emergency stop from {}", source);
+        TRIGGERED.signal(());
+        STATE.signal(EStopState::Stopped);
+    }
+}
+
+pub fn rearm() {
+    match LATCH.lock(|latch| latch.borrow_mut().rearm()) {
+        Ok(()) => {
+            info!("emergency stop re-armed");
+            STATE.signal(EStopState::Armed);
+        }
+        Err(e) => warn!("re-arm refused: {}", e),
+    }
+}
+
+pub fn stopped() -> bool {
+    LATCH.lock(|latch| latch.borrow().stopped())
+}
+
+/// notes a velocity command of the client, returns whether it may drive
+pub fn command(cmd: &VelocityCommand) -> bool {
+    LATCH.lock(|latch| latch.borrow_mut().command(cmd))
+}
+
+/// waits for the next trigger
+pub async fn triggered() {
+    TRIGGERED.wait().await
+}
+
+#[embassy_executor::task]
+pub async fn watch_button(mut button: Input<'static, AnyPin>) {
+    loop {
+        button.wait_for_falling_edge().await;
+        trigger("button A");
+    }
+}
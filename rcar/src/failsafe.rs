@@ -0,0 +1,107 @@
+//! Stops the car when the controller goes quiet or the link drops
+//!
+//! The [`Watchdog`] is fed by the motor task with every velocity command.
+//! When it trips the target drops to zero, so the wheels ramp down with the
+//! braking limits of `rcdrive::MotionProfile`, and a fault is latched in [`FAULTS`].
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use defmt::warn;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant};
+use rcproto::Faults;
+
+use crate::sound::{self, Sound};
+
+pub static FAULTS: FaultLatch = FaultLatch::new();
+
+static LINK_LOST: AtomicBool = AtomicBool::new(false);
+
+/// to be called when the connection of the driver closes
+pub fn link_lost() {
+    LINK_LOST.store(true, Ordering::Relaxed);
+}
+
+/// faults raised by the car, cleared only by the client
+pub struct FaultLatch {
+    bits: AtomicU8,
+    changed: Signal<ThreadModeRawMutex, Faults>,
+}
+
+impl FaultLatch {
+    const fn new() -> Self {
+        FaultLatch {
+            bits: AtomicU8::new(0),
+            changed: Signal::new(),
+        }
+    }
+
+    pub fn get(&self) -> Faults {
+        Faults::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub fn raise(&self, fault: Faults) {
+        let old = self.bits.fetch_or(fault.bits(), Ordering::Relaxed);
+        if old | fault.bits() != old {
+            self.changed.signal(self.get());
+        }
+    }
+
+    pub fn clear(&self) {
+        self.bits.store(0, Ordering::Relaxed);
+        self.changed.signal(Faults::empty());
+    }
+
+    /// waits for the latch to change and returns the new state
+    pub async fn changed(&self) -> Faults {
+        self.changed.wait().await
+    }
+}
+
+pub struct Watchdog {
+    timeout: Duration,
+    /// only armed while there is a command to time out
+    last_command: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(timeout_ms: u16) -> Self {
+        Watchdog {
+            timeout: Duration::from_millis(timeout_ms as u64),
+            last_command: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout_ms: u16) {
+        self.timeout = Duration::from_millis(timeout_ms as u64);
+    }
+
+    pub fn feed(&mut self) {
+        self.last_command = Some(Instant::now());
+    }
+
+    /// stops timing out until the next command, e.g. while commands are ignored
+    pub fn disarm(&mut self) {
+        self.last_command = None;
+    }
+
+    /// returns true when the car has to stop
+    pub fn check(&mut self) -> bool {
+        let link_lost = LINK_LOST.swap(false, Ordering::Relaxed);
+        let Some(last) = self.last_command else {
+            return false;
+        };
+        let fault = if link_lost {
+            Faults::LINK_LOST
+        } else if last.elapsed() > self.timeout {
+            Faults::COMMAND_TIMEOUT
+        } else {
+            return false;
+        };
+        warn!("failsafe tripped: {}", fault);
+        FAULTS.raise(fault);
+        sound::play(Sound::Failsafe);
+        self.last_command = None;
+        true
+    }
+}
@@ -1,16 +1,39 @@
 #![no_std]
 #![macro_use]
 
+pub mod battery;
 pub mod ble;
+pub mod bonds;
 
+pub mod compass;
+pub mod control;
+pub mod display;
+pub mod driver;
+
+pub mod encoder;
+pub mod estop;
+pub mod failsafe;
+pub mod line;
+pub mod link;
 pub mod motor;
+pub mod script;
+pub mod settings;
+pub mod sonar;
+pub mod sound;
 
 use embassy_nrf::{config::Config, interrupt::Priority};
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, signal::Signal};
+use rcproto::{MotionLimits, PidGains, VelocityCommand};
 
 // pub type SharedRpm = Mutex<ThreadModeRawMutex, f32>;
 // pub type SharedSpeed = Mutex<ThreadModeRawMutex, [f32; 2]>;
-pub type SharedSpeed = Signal<ThreadModeRawMutex, [f32; 3]>;
+pub type SharedSpeed = Signal<ThreadModeRawMutex, VelocityCommand>;
+pub type SharedGains = Signal<ThreadModeRawMutex, PidGains>;
+pub type SharedLimits = Signal<ThreadModeRawMutex, MotionLimits>;
+/// failsafe timeout in milliseconds
+pub type SharedTimeout = Signal<ThreadModeRawMutex, u16>;
+/// radians counterclockwise from the zeroed heading
+pub type SharedHeading = Mutex<ThreadModeRawMutex, f32>;
 
 pub fn config() -> Config {
     let mut config = Config::default();
@@ -0,0 +1,80 @@
+//! Line following with the IR sensors on pins 12 to 16, see `rcdrive::line`
+//!
+//! The sensors share their pins with the h-bridges and the encoders, so they
+//! are only read with the i2c motor boards. The analog pins are all taken,
+//! so the sensors have to be the kind with a digital output.
+//!
+//! Like the script runner, the follower is shared with a blocking mutex and
+//! driven by the motor task.
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_nrf::gpio::{AnyPin, Input, Level, Pull};
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use rcdrive::line::LineFollower;
+use rcproto::{LineFollow, VelocityCommand, LINE_SENSORS_MAX};
+
+/// TCRT5000 modules pull their output high over a dark line
+const ON_LINE: Level = Level::High;
+
+const SENSORS: usize = LINE_SENSORS_MAX as usize;
+
+struct Follower {
+    /// leftmost first
+    sensors: [Input<'static, AnyPin>; SENSORS],
+    follower: LineFollower,
+}
+
+static FOLLOWER: Mutex<ThreadModeRawMutex, RefCell<Option<Follower>>> =
+    Mutex::new(RefCell::new(None));
+
+/// sets up the sensor pins, leftmost first
+pub fn install(pins: [AnyPin; SENSORS]) {
+    let follower = Follower {
+        sensors: pins.map(|pin| Input::new(pin, Pull::None)),
+        follower: LineFollower::new(LineFollow::default()),
+    };
+    FOLLOWER.lock(|f| f.replace(Some(follower)));
+}
+
+pub fn configure(params: LineFollow) {
+    FOLLOWER.lock(|f| match f.borrow_mut().as_mut() {
+        Some(f) => {
+            info!("line follow: {}", params);
+            f.follower.params = params;
+            f.follower.reset();
+        }
+        None => warn!("no line sensors on this car"),
+    });
+}
+
+/// hands control back to the joystick, e.g. when the failsafe trips
+pub fn stop() {
+    FOLLOWER.lock(|f| {
+        if let Some(f) = f.borrow_mut().as_mut() {
+            if f.follower.params.enabled {
+                info!("line follow stopped");
+                f.follower.params.enabled = false;
+            }
+        }
+    });
+}
+
+/// the command for the next `dt` seconds, if the line follower is in control
+pub fn tick(dt: f32) -> Option<VelocityCommand> {
+    FOLLOWER.lock(|f| {
+        let mut f = f.borrow_mut();
+        let f = f.as_mut().filter(|f| f.follower.params.enabled)?;
+        let mut readings = [0.0; SENSORS];
+        let used = f.follower.params.sensors as usize;
+        for (r, sensor) in readings.iter_mut().zip(&f.sensors).take(used) {
+            *r = if sensor.get_level() == ON_LINE {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        Some(f.follower.update(&readings[..used], dt))
+    })
+}
@@ -0,0 +1,67 @@
+//! Asking each client for a fast link, see `rcproto::link`
+//!
+//! The car only gets to ask, the client decides. Intervals are tried fastest
+//! first until the client settles on one of them. The softdevice doesn't tell
+//! which PHY and packet length the client agreed to, so those are reported as
+//! unknown once asked for, or as the defaults when asking failed.
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+use nrf_softdevice::ble::{Connection, Phy};
+use nrf_softdevice::raw;
+use rcproto::{LinkParams, LinkPhy};
+
+/// how long the client gets to answer a connection parameter request
+const NEGOTIATION_TIME: Duration = Duration::from_secs(1);
+
+/// negotiates `conn` down to the fastest interval the client allows
+pub async fn tune(conn: &mut Connection, slot: usize) -> LinkParams {
+    let phy = match conn.phy_update(Phy::M2, Phy::M2) {
+        // the softdevice doesn't say what the peer agreed to
+        Ok(()) => LinkPhy::Unknown,
+        Err(e) => {
+            warn!("slot {}: 2M phy refused, staying on 1M: {}", slot, e);
+            LinkPhy::M1
+        }
+    };
+    let data_length = match conn.data_length_update(None) {
+        Ok(_) => rcproto::DATA_LENGTH_UNKNOWN,
+        Err(e) => {
+            warn!("slot {}: data length extension refused: {}", slot, e);
+            rcproto::DATA_LENGTH_DEFAULT
+        }
+    };
+    let mut link = LinkParams {
+        phy,
+        data_length,
+        ..LinkParams::default()
+    };
+    for step in rcproto::INTERVAL_STEPS {
+        let params = raw::ble_gap_conn_params_t {
+            min_conn_interval: step.0,
+            max_conn_interval: step.1,
+            slave_latency: rcproto::SLAVE_LATENCY,
+            conn_sup_timeout: rcproto::SUPERVISION_TIMEOUT,
+        };
+        if let Err(e) = conn.set_conn_params(params) {
+            warn!("slot {}: interval {} refused: {}", slot, step, e);
+            continue;
+        }
+        Timer::after(NEGOTIATION_TIME).await;
+        let now = conn.conn_params();
+        link.interval = now.max_conn_interval;
+        link.latency = now.slave_latency;
+        link.timeout = now.conn_sup_timeout;
+        if link.meets(step) {
+            break;
+        }
+        warn!("slot {}: client didn't take interval {}", slot, step);
+    }
+    info!(
+        "slot {}: link every {}us, {}",
+        slot,
+        link.interval_us(),
+        link
+    );
+    link
+}
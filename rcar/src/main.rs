@@ -1,9 +1,9 @@
 #![no_std]
 #![no_main]
 
-use defmt::{info, println};
+use defmt::{info, println, warn};
 use embassy_executor::Spawner;
-use embassy_nrf::gpio::{Level, Output, OutputDrive};
+use embassy_nrf::gpio::{Input, Level, Output, OutputDrive, Pin, Pull};
 use embassy_time::Timer;
 use {defmt_rtt as _, panic_probe as _};
 
@@ -14,24 +14,187 @@ use nrf_softdevice::ble::gatt_server::Service;
 use nrf_softdevice::ble::{gatt_server, get_address, peripheral, set_address, Address, Connection};
 use nrf_softdevice::{raw, Softdevice};
 
-use rcar::SharedSpeed;
+use rcar::driver::{Driver, I2cPins};
+use rcar::settings::{self, SharedConfig};
+use rcar::{SharedGains, SharedHeading, SharedLimits, SharedSpeed, SharedTimeout};
+use rcproto::{CarConfig, MotorDriverKind};
+use static_cell::StaticCell;
 
 pub static TARGET_SPEED: SharedSpeed = SharedSpeed::new();
+pub static PID_GAINS: SharedGains = SharedGains::new();
+pub static MOTION_LIMITS: SharedLimits = SharedLimits::new();
+pub static FAILSAFE_TIMEOUT: SharedTimeout = SharedTimeout::new();
+pub static STORED_CONFIG: SharedConfig = SharedConfig::new();
+pub static HEADING: SharedHeading = SharedHeading::new(0.0);
+static CAR_CONFIG: StaticCell<CarConfig> = StaticCell::new();
 
 #[embassy_executor::main]
 async fn main(s: Spawner) {
     println!("Hello, World!");
     let p = embassy_nrf::init(rcar::config());
+    let car_config: &'static CarConfig = CAR_CONFIG.init(settings::load());
+
+    &TARGET_SPEED.signal(rcproto::VelocityCommand::default());
+
+    // encoders on the edge connector: front left p12/p13 (QDEC), front right p14/p15,
+    // back left p16/p1, back right p8/p9, hall sensors use the first pin of each pair
+    #[cfg(feature = "encoders")]
+    {
+        use rcar::encoder::{count_hall, count_qdec, count_quadrature, Wheel};
+        use rcproto::EncoderKind;
+
+        match car_config.encoders {
+            EncoderKind::Quadrature => {
+                s.spawn(count_qdec(
+                    Wheel::FrontLeft,
+                    p.QDEC,
+                    p.P0_12.degrade(),
+                    p.P0_17.degrade(),
+                ))
+                .unwrap();
+                let quad = [
+                    (Wheel::FrontRight, p.P0_01.degrade(), p.P0_13.degrade()),
+                    (Wheel::BackLeft, p.P1_02.degrade(), p.P0_03.degrade()),
+                    (Wheel::BackRight, p.P0_10.degrade(), p.P0_09.degrade()),
+                ];
+                for (wheel, a, b) in quad {
+                    let a = Input::new(a, Pull::Up);
+                    let b = Input::new(b, Pull::Up);
+                    s.spawn(count_quadrature(wheel, a, b)).unwrap();
+                }
+            }
+            EncoderKind::Hall => {
+                let hall = [
+                    (Wheel::FrontLeft, p.P0_12.degrade()),
+                    (Wheel::FrontRight, p.P0_01.degrade()),
+                    (Wheel::BackLeft, p.P1_02.degrade()),
+                    (Wheel::BackRight, p.P0_10.degrade()),
+                ];
+                for (wheel, pulse) in hall {
+                    let pulse = Input::new(pulse, Pull::Up);
+                    s.spawn(count_hall(wheel, pulse)).unwrap();
+                }
+            }
+            EncoderKind::None => info!("no encoders configured, running open loop"),
+        }
+    }
+    if !rcar::encoder::fits(car_config) {
+        warn!("encoders configured, but this build has no pins for them, running open loop");
+    }
+
+    let matrix = rcar::display::matrix(
+        [
+            p.P0_21.degrade(),
+            p.P0_22.degrade(),
+            p.P0_15.degrade(),
+            p.P0_24.degrade(),
+            p.P0_19.degrade(),
+        ],
+        [
+            p.P0_28.degrade(),
+            p.P0_11.degrade(),
+            p.P0_31.degrade(),
+            p.P1_05.degrade(),
+            p.P0_30.degrade(),
+        ],
+    );
+    s.spawn(rcar::display::show_status(matrix)).unwrap();
+    s.spawn(rcar::sound::play_sounds(p.PWM2, p.P0_00)).unwrap();
+    let button_a = Input::new(p.P0_14.degrade(), Pull::Up);
+    s.spawn(rcar::estop::watch_button(button_a)).unwrap();
+
+    s.spawn(rcar::compass::read_heading(
+        p.TWISPI0,
+        p.P0_08,
+        p.P0_16,
+        &HEADING,
+        car_config.hard_iron,
+    ))
+    .unwrap();
+
+    if car_config.battery.divider > 0.0 {
+        s.spawn(rcar::battery::monitor_battery(
+            p.SAADC,
+            p.P0_04,
+            car_config.battery,
+        ))
+        .unwrap();
+    } else {
+        info!("no battery divider, not monitoring the pack");
+    }
+
+    if car_config.sonar && !rcar::sonar::fits(car_config) {
+        warn!("sonar configured, but its pins are taken in this build, no automatic braking");
+    }
+    let driver = match car_config.driver {
+        #[cfg(all(feature = "hbridge", not(feature = "encoders")))]
+        MotorDriverKind::HBridge => Driver::hbridge(
+            p.PWM0,
+            p.PWM1,
+            [
+                p.P0_02.degrade(),
+                p.P0_03.degrade(),
+                p.P0_10.degrade(),
+                p.P0_12.degrade(),
+                p.P0_17.degrade(),
+                p.P0_01.degrade(),
+                p.P0_13.degrade(),
+                p.P1_02.degrade(),
+            ],
+        ),
+        kind => {
+            // the pins the h-bridges would take are free for the sonar and
+            // the line sensors
+            #[cfg(not(feature = "encoders"))]
+            {
+                if car_config.sonar {
+                    s.spawn(rcar::sonar::measure_distance(
+                        p.P0_02.degrade(),
+                        p.P0_03.degrade(),
+                        p.GPIOTE_CH0,
+                        p.PPI_CH0,
+                        p.TIMER1,
+                    ))
+                    .unwrap();
+                } else {
+                    info!("no sonar fitted");
+                }
+                rcar::line::install([
+                    p.P0_12.degrade(),
+                    p.P0_17.degrade(),
+                    p.P0_01.degrade(),
+                    p.P0_13.degrade(),
+                    p.P1_02.degrade(),
+                ]);
+            }
+            let pins = I2cPins {
+                twi: p.TWISPI1,
+                scl: p.P0_26,
+                sda: p.P1_00,
+            };
+            Driver::i2c(kind, car_config.i2c_address, pins)
+        }
+    };
 
-    &TARGET_SPEED.signal([0.0; 3]);
     s.spawn(rcar::motor::drive_servos(
+        car_config,
         &TARGET_SPEED,
-        p.TWISPI1,
-        p.P0_26,
-        p.P1_00,
+        &PID_GAINS,
+        &MOTION_LIMITS,
+        &FAILSAFE_TIMEOUT,
+        &HEADING,
+        driver,
     ))
     .unwrap();
 
-    s.spawn(rcar::ble::read_ble(s, "rcar", &TARGET_SPEED))
-        .unwrap();
+    s.spawn(rcar::ble::read_ble(
+        s,
+        car_config,
+        &TARGET_SPEED,
+        &PID_GAINS,
+        &MOTION_LIMITS,
+        &FAILSAFE_TIMEOUT,
+        &STORED_CONFIG,
+    ))
+    .unwrap();
 }
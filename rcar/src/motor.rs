@@ -1,9 +1,17 @@
 #![no_std]
 #![no_main]
 
-use core::{any::Any, ops::Mul, time};
-
-use crate::{ble, SharedSpeed};
+use core::{any::Any, time};
+
+use crate::{
+    battery, ble, compass, display,
+    driver::Driver,
+    encoder::ENCODERS,
+    estop,
+    failsafe::{Watchdog, FAULTS},
+    line, script, sonar, sound, SharedGains, SharedHeading, SharedLimits, SharedSpeed,
+    SharedTimeout,
+};
 use defmt::{debug, error, info, println, trace, warn, Debug2Format, Format};
 use embassy_executor::Spawner;
 use embassy_futures::select::{select, Either};
@@ -16,14 +24,13 @@ use embassy_nrf::{
     twim::{self, Twim},
 };
 
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
-use embassy_time::{Duration, Instant, Timer};
-use micromath::{
-    vector::{F32x2, Vector},
-    F32Ext,
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use rcdrive::{driver::Supervisor, MotorDriver, Odometry, Pid, Pipeline, SpeedMeter, WheelSpeed};
+use rcproto::{
+    CarConfig, Faults, Flags, Gear, MotionLimits, MotorErrors, PidGains, Pose, VelocityCommand,
 };
 use {defmt_rtt as _, panic_probe as _};
-type Vec2 = F32x2;
 
 // use nrf_softdevice::ble::{gatt_server, peripheral, Connection};
 // use nrf_softdevice::{raw, Softdevice};
@@ -32,187 +39,207 @@ use static_cell::ConstStaticCell;
 use static_cell::StaticCell;
 // type SharedCounter = Mutex<ThreadModeRawMutex, u32>;
 // static COUNTER: SharedCounter = SharedCounter::new(0);
-use core::ops::Add;
-
-bind_interrupts!(struct Irqs {
-    SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1 => twim::InterruptHandler<peripherals::TWISPI1>;
-});
-
-#[derive(Clone, Default, Copy, Debug)]
-struct WheelSpeed {
-    front_left: f32,
-    front_right: f32,
-    back_left: f32,
-    back_right: f32,
+
+/// period of the speed control loop
+const CONTROL_PERIOD: Duration = Duration::from_millis(20);
+/// encoder counts per second with the motor at full output
+const FULL_SPEED_CPS: f32 = 1200.0;
+/// ground speed of a wheel at full output, in m/s
+const FULL_SPEED_MPS: f32 = 0.6;
+/// half the track plus half the wheelbase, in m
+const WHEEL_LEVER: f32 = 0.15;
+
+/// latest dead reckoned pose, published every control period
+pub static POSE: Signal<ThreadModeRawMutex, Pose> = Signal::new();
+/// resets the dead reckoned pose to the origin
+pub static RESET_POSE: Signal<ThreadModeRawMutex, ()> = Signal::new();
+/// speed gear selected by the client
+pub static GEAR: Signal<ThreadModeRawMutex, Gear> = Signal::new();
+/// failed outputs per motor, signalled when they go up
+pub static MOTOR_ERRORS: Signal<ThreadModeRawMutex, MotorErrors> = Signal::new();
+
+/// per wheel PID on top of the open loop target, for wheels with an encoder
+struct SpeedCtrl {
+    gains: PidGains,
+    pids: [Pid; 4],
+    meter: SpeedMeter,
+    measured: [Option<f32>; 4],
 }
-impl WheelSpeed {
-    fn drive_y(y: f32) -> WheelSpeed {
-        WheelSpeed {
-            front_left: y,
-            back_left: y,
-            front_right: -y,
-            back_right: -y,
-        }
-    }
-    fn drive_x(x: f32) -> WheelSpeed {
-        WheelSpeed {
-            front_left: x,
-            back_left: -x,
-            front_right: x,
-            back_right: -x,
-        }
-    }
-    fn drive_z(z: f32) -> WheelSpeed {
-        WheelSpeed {
-            front_left: -z,
-            back_left: -z,
-            front_right: -z,
-            back_right: -z,
-        }
-    }
 
-    fn translate(x: f32, y: f32) -> WheelSpeed {
-        (WheelSpeed::drive_x(x) + WheelSpeed::drive_y(y)).clamp1()
-    }
-    fn trans_rotate(x: f32, y: f32, z: f32) -> WheelSpeed {
-        let xy_mag = Vec2 { x, y }.magnitude();
-        let z = z / (2.0 - xy_mag);
-        (WheelSpeed::drive_x(x) + WheelSpeed::drive_y(y) + WheelSpeed::drive_z(z)).clamp1()
+impl SpeedCtrl {
+    fn new(gains: PidGains) -> Self {
+        SpeedCtrl {
+            gains,
+            pids: [Pid::default(); 4],
+            meter: SpeedMeter::new(FULL_SPEED_CPS, ENCODERS.counts()),
+            measured: [None; 4],
+        }
     }
 
-    fn to_array(&self) -> [f32; 4] {
-        [
-            self.front_left,
-            self.back_left,
-            self.front_right,
-            self.back_right,
-        ]
-    }
-    fn absmax(&self) -> f32 {
-        self.to_array()
-            .map(|e| e.abs())
-            .iter()
-            .fold(0.0, |acc, v| acc.max(*v))
+    fn set_gains(&mut self, gains: PidGains) {
+        info!("new wheel gains: {}", gains);
+        self.gains = gains;
+        self.pids.iter_mut().for_each(Pid::reset);
     }
 
-    fn clamp1(mut self) -> WheelSpeed {
-        let max = self.absmax();
-        if max < 1.0 {
-            return self;
+    fn track(&mut self, target: WheelSpeed, dt: f32) -> WheelSpeed {
+        let target = target.to_array();
+        self.measured = self
+            .meter
+            .measure(ENCODERS.counts(), ENCODERS.kinds(), dt, &target);
+        let mut out = target;
+        for i in 0..4 {
+            let Some(speed) = self.measured[i] else {
+                continue;
+            };
+            if target[i] == 0.0 {
+                // let a stopped wheel rest instead of holding it in place
+                self.pids[i].reset();
+                continue;
+            }
+            let correction = self.pids[i].update(&self.gains, target[i] - speed, dt);
+            out[i] = (target[i] + correction).clamp(-1.0, 1.0);
         }
-        self * (1.0 / max)
+        WheelSpeed::from_array(out)
     }
-}
-
-impl Add<f32> for WheelSpeed {
-    type Output = WheelSpeed;
 
-    fn add(mut self, rhs: f32) -> Self::Output {
-        self.front_left += rhs;
-        self.back_left += rhs;
-        self.front_right += rhs;
-        self.back_right += rhs;
-        self
-    }
-}
-impl Mul<f32> for WheelSpeed {
-    type Output = WheelSpeed;
-
-    fn mul(mut self, rhs: f32) -> Self::Output {
-        self.front_left *= rhs;
-        self.back_left *= rhs;
-        self.front_right *= rhs;
-        self.back_right *= rhs;
-        self
-    }
-}
-
-impl Add for WheelSpeed {
-    type Output = WheelSpeed;
-
-    fn add(mut self, rhs: Self) -> Self::Output {
-        self.front_left += rhs.front_left;
-        self.back_left += rhs.back_left;
-        self.front_right += rhs.front_right;
-        self.back_right += rhs.back_right;
-        self
-    }
-}
-
-#[derive(Clone, Copy, defmt::Format)]
-struct WheelMan {
-    front_left: u8,
-    front_right: u8,
-    back_left: u8,
-    back_right: u8,
-}
-
-type MotorWriteBufs = [[u8; 2]; 4];
-
-impl WheelMan {
-    fn transform_bufs(&self, x: f32, y: f32) -> MotorWriteBufs {
-        let speeds = WheelSpeed::translate(x, y);
-        [
-            [self.front_left, (speeds.front_left * 90.0 + 90.0) as u8],
-            [self.front_right, (speeds.front_right * 90.0 + 90.0) as u8],
-            [self.back_left, (speeds.back_left * 90.0 + 90.0) as u8],
-            [self.back_right, (speeds.back_right * 90.0 + 90.0) as u8],
-        ]
-    }
-    fn trans_rotate_bufs(&self, x: f32, y: f32, z: f32) -> MotorWriteBufs {
-        let speeds = WheelSpeed::trans_rotate(x, y, z);
-        [
-            [self.front_left, (speeds.front_left * 90.0 + 90.0) as u8],
-            [self.front_right, (speeds.front_right * 90.0 + 90.0) as u8],
-            [self.back_left, (speeds.back_left * 90.0 + 90.0) as u8],
-            [self.back_right, (speeds.back_right * 90.0 + 90.0) as u8],
-        ]
+    /// the measured speed of wheels with an encoder, `applied` for the others
+    fn actual(&self, applied: WheelSpeed) -> WheelSpeed {
+        let applied = applied.to_array();
+        WheelSpeed::from_array(core::array::from_fn(|i| {
+            self.measured[i].unwrap_or(applied[i])
+        }))
     }
 }
 
 #[embassy_executor::task]
 pub async fn drive_servos(
+    config: &'static CarConfig,
     target_speed: &'static SharedSpeed,
-    twi1: TWISPI1,
-    scl: P0_26,
-    sda: P1_00,
+    gains: &'static SharedGains,
+    limits: &'static SharedLimits,
+    timeout: &'static SharedTimeout,
+    heading: &'static SharedHeading,
+    mut driver: Driver,
 ) {
-    info!("Initializing TWI...");
-
-    let mut i2c_config = twim::Config::default();
-    i2c_config.frequency = twim::Frequency::K100;
-    i2c_config.sda_pullup = true;
-    i2c_config.scl_pullup = true;
-    i2c_config.sda_high_drive = true;
-    i2c_config.scl_high_drive = true;
-
-    interrupt::SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1.set_priority(interrupt::Priority::P5);
-    let mut twim = Twim::new(twi1, Irqs, sda, scl, i2c_config);
-    let wukong_address = 0x10;
-    let wheel_cfg = WheelMan {
-        front_left: 4,
-        back_left: 5,
-        front_right: 6,
-        back_right: 7,
-    };
-
-    // let mut speed = 0_u8;
+    info!("motor driver: {}", config.driver);
+    if let Err(e) = driver.init().await {
+        error!("failed to init motor driver: {}", e);
+    }
+    let mut pipeline = Pipeline::new(config, MotionLimits::default());
+    let model = pipeline.model();
+    info!("kinematics: {}", config.kinematics);
+
+    let mut ctrl = SpeedCtrl::new(PidGains::default());
+    let mut target = VelocityCommand::default();
+    let mut gear = Gear::default();
+    let mut watchdog = Watchdog::new(rcproto::FAILSAFE_TIMEOUT_MS);
+    let mut odometry = Odometry::new(FULL_SPEED_MPS, WHEEL_LEVER);
+    let mut supervisor = Supervisor::default();
+    let mut last_bufs = None;
+    let mut ticker = Ticker::every(CONTROL_PERIOD);
+    let mut last_tick = Instant::now();
+    let mut headless_refused = false;
     info!("entering speed ctrl loop");
     loop {
-        let [x, y, z] = target_speed.wait().await;
-        let mut motor_speeds = wheel_cfg.trans_rotate_bufs(x, y, z);
-        trace!("new speed: x:{}, y:{}, z:{}", x, y, z);
-
-        for (i, [motor, speed]) in motor_speeds.iter().copied().enumerate() {
-            let buf = [motor, speed, 0, 0];
-            let res = twim.write(wukong_address, &buf).await;
-            match res {
-                _ => {}
-                Err(e) => {
-                    error!(
-                        "failed to write twi_buff: {}:{:?} \n\te:{}",
-                        wukong_address, buf, e
-                    );
+        // an emergency stop doesn't wait for the next tick
+        select(ticker.next(), estop::triggered()).await;
+        let now = Instant::now();
+        let dt_us = (now - last_tick).as_micros() as u32;
+        let dt = dt_us as f32 / 1_000_000.0;
+        last_tick = now;
+
+        if let Some(g) = gains.try_take() {
+            ctrl.set_gains(g);
+        }
+        if let Some(l) = limits.try_take() {
+            info!("new motion limits: {}", l);
+            pipeline.profile.limits = l;
+        }
+        if let Some(ms) = timeout.try_take() {
+            info!("new failsafe timeout: {}ms", ms);
+            watchdog.set_timeout(ms);
+        }
+        if let Some(g) = GEAR.try_take() {
+            info!("gear: {}", g);
+            gear = g;
+            display::GEAR.signal(g);
+        }
+        if let Some(cmd) = target_speed.try_take() {
+            trace!("new speed: x:{}, y:{}, z:{}", cmd.x, cmd.y, cmd.z);
+            target = cmd;
+            watchdog.feed();
+        }
+        let battery_limit = battery::speed_limit();
+        let stopped = estop::stopped();
+        if stopped {
+            // velocity commands are ignored, their absence is no fault
+            watchdog.disarm();
+            pipeline.profile.stop();
+            // repeat the neutral frame, in case a motor missed it
+            last_bufs = None;
+        }
+        if watchdog.check() || supervisor.lost() || battery_limit == 0.0 || stopped {
+            target = VelocityCommand::default();
+            script::abort();
+            line::stop();
+            compass::abort();
+        }
+        // a compass calibration, a running script or the line follower
+        // ignore the joystick
+        let mut command = gear
+            .apply(
+                &compass::tick()
+                    .or_else(|| script::tick(dt_us))
+                    .or_else(|| line::tick(dt))
+                    .unwrap_or(target),
+            )
+            .scaled(battery_limit);
+        // without a calibrated compass the heading is anybody's guess
+        let refuse = command.flags.contains(Flags::HEADLESS) && !compass::calibrated();
+        if refuse != headless_refused {
+            headless_refused = refuse;
+            if refuse {
+                warn!("compass not calibrated, headless driving refused");
+            }
+        }
+        if refuse {
+            command = VelocityCommand::default();
+        }
+
+        display::COMMAND.signal(command);
+
+        let facing = *heading.lock().await;
+        let tick = pipeline.tick(&command, facing, sonar::blocked(), dt, |wheels| {
+            ctrl.track(wheels, dt)
+        });
+        sound::update_reversing(tick.body[1]);
+        if RESET_POSE.try_take().is_some() {
+            info!("pose reset");
+            odometry.reset();
+        }
+        POSE.signal(odometry.update(model, &ctrl.actual(tick.applied), dt));
+
+        let motor_speeds = tick.bufs;
+        if last_bufs == Some(motor_speeds) {
+            continue;
+        }
+
+        match supervisor.write(&mut driver, &motor_speeds).await {
+            Ok(()) => last_bufs = Some(motor_speeds),
+            Err(e) => {
+                // write the whole frame again on the next tick
+                last_bufs = None;
+                warn!("failed to write motors: {}", e);
+                MOTOR_ERRORS.signal(MotorErrors(supervisor.errors()));
+                if supervisor.lost() && !FAULTS.get().contains(Faults::MOTOR_BOARD_LOST) {
+                    error!("motor board lost, stopping");
+                    FAULTS.raise(Faults::MOTOR_BOARD_LOST);
+                }
+                if supervisor.needs_recovery() {
+                    if let Err(e) = driver.recover().await {
+                        warn!("failed to recover motor driver: {}", e);
+                    }
                 }
             }
         }
@@ -0,0 +1,51 @@
+//! The motion script uploaded over BLE, run by the motor task
+//!
+//! The gatt server and the motor task both run in thread mode, so a blocking
+//! mutex is enough to share the runner.
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use rcdrive::script::ScriptRunner;
+use rcproto::{ScriptCommand, ScriptStatus, VelocityCommand};
+
+static RUNNER: Mutex<ThreadModeRawMutex, RefCell<ScriptRunner>> =
+    Mutex::new(RefCell::new(ScriptRunner::DEFAULT));
+
+/// signalled whenever the status of the script changes
+pub static STATUS: Signal<ThreadModeRawMutex, ScriptStatus> = Signal::new();
+
+/// runs `f` on the runner and reports the status if it changed
+fn with_runner<R>(f: impl FnOnce(&mut ScriptRunner) -> R) -> R {
+    RUNNER.lock(|runner| {
+        let mut runner = runner.borrow_mut();
+        let before = runner.status();
+        let result = f(&mut runner);
+        let after = runner.status();
+        if after != before {
+            info!("script: {}", after);
+            STATUS.signal(after);
+        }
+        result
+    })
+}
+
+pub fn apply(command: &ScriptCommand) {
+    if let Err(e) = with_runner(|runner| runner.apply(command)) {
+        warn!("script command {} refused: {}", command, e);
+    }
+}
+
+/// stops a running script, e.g. when the failsafe trips
+pub fn abort() {
+    with_runner(ScriptRunner::abort);
+}
+
+/// the scripted command for the next `dt_us`, if a script is in control
+pub fn tick(dt_us: u32) -> Option<VelocityCommand> {
+    with_runner(|runner| runner.tick(dt_us))
+}
@@ -0,0 +1,91 @@
+//! Persistent [`CarConfig`] in the last page of the internal flash
+//!
+//! The page is left out of `FLASH` in `memory.x`. It is read directly at boot,
+//! before the softdevice runs, and written through the softdevice afterwards.
+//! A new config takes effect on the next boot, except for the compass offset,
+//! which only ever comes from a calibration and is used at once.
+
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, signal::Signal};
+use embedded_storage_async::nor_flash::NorFlash;
+use nrf_softdevice::Flash;
+use rcproto::{CarConfig, RecordError, CONFIG_LEN};
+
+/// start of the page reserved in `memory.x`
+const CONFIG_PAGE: u32 = 0x7F000;
+pub(crate) const PAGE_SIZE: u32 = 4096;
+const MAGIC: [u8; 4] = *b"RCFG";
+const RECORD_LEN: usize = rcproto::record_len(CONFIG_LEN);
+
+pub type SharedConfig = Signal<ThreadModeRawMutex, CarConfig>;
+/// the softdevice hands out its flash only once, the config and the bonds share it
+pub type SharedFlash = Mutex<ThreadModeRawMutex, Flash>;
+
+/// a new magnetometer offset from `compass`, patched into the stored config
+pub static HARD_IRON: Signal<ThreadModeRawMutex, [f32; 2]> = Signal::new();
+
+fn decode_record(record: &[u8]) -> Option<CarConfig> {
+    let body = match rcproto::unframe(MAGIC, record, CONFIG_LEN) {
+        Ok(body) => body,
+        Err(RecordError::Missing) => return None,
+        Err(RecordError::Checksum) => {
+            warn!("config checksum mismatch");
+            return None;
+        }
+    };
+    match CarConfig::decode(body) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("stored config unusable: {}", e);
+            None
+        }
+    }
+}
+
+/// the stored config, or the defaults if there is none
+pub fn load() -> CarConfig {
+    let record = unsafe { rcproto::read_flash(CONFIG_PAGE, RECORD_LEN) };
+    match decode_record(record) {
+        Some(config) => {
+            info!("loaded config: {}", config);
+            config
+        }
+        None => {
+            info!("no stored config, using defaults");
+            CarConfig::default()
+        }
+    }
+}
+
+/// writes every config signalled by the gatt server to flash, keeping the
+/// compass offset of the calibration
+#[embassy_executor::task]
+pub async fn store_config(
+    flash: &'static SharedFlash,
+    booted: &'static CarConfig,
+    pending: &'static SharedConfig,
+) {
+    let mut hard_iron = booted.hard_iron;
+    let mut latest = *booted;
+    loop {
+        match select(pending.wait(), HARD_IRON.wait()).await {
+            Either::First(config) => latest = config,
+            Either::Second(offset) => hard_iron = Some(offset),
+        }
+        let config = CarConfig {
+            hard_iron,
+            ..latest
+        };
+        let record: [u8; RECORD_LEN] = rcproto::frame(MAGIC, &config.encode());
+        let mut flash = flash.lock().await;
+        let res = match flash.erase(CONFIG_PAGE, CONFIG_PAGE + PAGE_SIZE).await {
+            Ok(()) => flash.write(CONFIG_PAGE, &record).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => info!("stored config, applied on next boot: {}", config),
+            Err(e) => warn!("failed to store config: {}", e),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! HC-SR04 ultrasonic sensor at the front of the car, see `rcdrive::obstacle`
+//!
+//! Trigger on ring pin 0 and echo on ring pin 1, so only with the i2c motor
+//! boards and without encoders, and only when the config says a sensor is
+//! fitted. The sensor runs on 5V, its echo needs a divider down to 3.3V.
+//!
+//! The echo is timed in hardware: both of its edges fire the same GPIOTE
+//! event, which PPI routes to START and CAPTURE[0] of a 1MHz TIMER. The
+//! rising edge starts the timer from zero, the falling one captures the
+//! width in µs, without any interrupt latency in between.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+use embassy_nrf::gpio::{AnyPin, Input, Level, Output, OutputDrive, Pull};
+use embassy_nrf::gpiote::{InputChannel, InputChannelPolarity};
+use embassy_nrf::peripherals::{GPIOTE_CH0, PPI_CH0, TIMER1};
+use embassy_nrf::ppi::Ppi;
+use embassy_nrf::timer::{self, Frequency};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, signal::Signal};
+use embassy_time::{Duration, Ticker, Timer};
+use rcdrive::obstacle::{echo_distance_mm, ObstacleDetector, MAX_ECHO_US, STOP_MM};
+use rcproto::{CarConfig, MotorDriverKind, NO_OBSTACLE};
+
+/// the datasheet asks for 60ms between pings, so echoes can die down
+const PING_PERIOD: Duration = Duration::from_millis(60);
+/// the sensor sends its burst and raises echo well within this
+const ECHO_START_TIMEOUT: Duration = Duration::from_millis(5);
+/// shorter than any real echo, what the rising edge alone captures
+const MIN_ECHO_US: u32 = 100;
+
+/// distance ahead in mm, [`NO_OBSTACLE`] when nothing is in range
+pub static DISTANCE: Signal<ThreadModeRawMutex, u16> = Signal::new();
+
+static BLOCKED: AtomicBool = AtomicBool::new(false);
+
+/// an obstacle is too close to drive forward
+pub fn blocked() -> bool {
+    BLOCKED.load(Ordering::Relaxed)
+}
+
+/// whether this build leaves the sensor its pins with `config`, the encoders
+/// and the h-bridges take them
+pub fn fits(config: &CarConfig) -> bool {
+    let hbridge = cfg!(feature = "hbridge") && config.driver == MotorDriverKind::HBridge;
+    !cfg!(feature = "encoders") && !hbridge
+}
+
+/// distance to the obstacle in mm, `None` when the echo didn't both rise
+/// and fall in time
+async fn ping(
+    trig: &mut Output<'static, AnyPin>,
+    timer: &timer::Timer<'static, TIMER1>,
+) -> Option<u16> {
+    timer.stop();
+    timer.clear();
+    timer.cc(0).write(0);
+    trig.set_high();
+    Timer::after_micros(10).await;
+    trig.set_low();
+    Timer::after(ECHO_START_TIMEOUT + Duration::from_micros(MAX_ECHO_US as u64)).await;
+    timer.stop();
+    // 0 without an echo, about 0 when it never fell
+    let width = timer.cc(0).read();
+    if width < MIN_ECHO_US {
+        return None;
+    }
+    echo_distance_mm(width)
+}
+
+#[embassy_executor::task]
+pub async fn measure_distance(
+    trig: AnyPin,
+    echo: AnyPin,
+    gpiote_ch: GPIOTE_CH0,
+    ppi_ch: PPI_CH0,
+    timer: TIMER1,
+) {
+    let mut trig = Output::new(trig, Level::Low, OutputDrive::Standard);
+    // a sensor that isn't there reads as no echo, not as noise
+    let echo = Input::new(echo, Pull::Down);
+    let echo = InputChannel::new(gpiote_ch, echo, InputChannelPolarity::Toggle);
+    let timer = timer::Timer::new(timer);
+    timer.set_frequency(Frequency::F1MHz);
+    let mut edges = Ppi::new_one_to_two(
+        ppi_ch,
+        echo.event_in(),
+        timer.task_start(),
+        timer.cc(0).task_capture(),
+    );
+    edges.enable();
+
+    let mut detector = ObstacleDetector::new(STOP_MM);
+    let mut ticker = Ticker::every(PING_PERIOD);
+    loop {
+        ticker.next().await;
+        let distance = ping(&mut trig, &timer).await;
+        let blocked = detector.update(distance);
+        let distance = distance.unwrap_or(NO_OBSTACLE);
+        if BLOCKED.swap(blocked, Ordering::Relaxed) != blocked {
+            if blocked {
+                info!("obstacle ahead at {}mm, braking", distance);
+            } else {
+                info!("way ahead clear");
+            }
+        }
+        DISTANCE.signal(distance);
+    }
+}
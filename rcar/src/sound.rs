@@ -0,0 +1,93 @@
+//! Tones on the speaker of the micro:bit v2, on P0_00 through PWM2
+//!
+//! Sounds go through a queue to [`play_sounds`], so nobody waits for a tone
+//! to end. The pwm makes the square wave by itself, the task only wakes up
+//! between notes. While the car reverses it beeps between sounds.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::debug;
+use embassy_nrf::{
+    peripherals::{P0_00, PWM2},
+    pwm::{Prescaler, SimplePwm},
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
+use embassy_time::{with_timeout, Duration, Timer};
+
+/// pwm clock with [`Prescaler::Div16`]
+const PWM_CLOCK_HZ: u32 = 1_000_000;
+/// on and off time of the reverse beeper, in ms
+const BEEP_MS: u16 = 250;
+const BEEP_PERIOD: Duration = Duration::from_millis(BEEP_MS as u64);
+/// reversing slower than this doesn't beep
+const REVERSING_BELOW: f32 = -0.05;
+
+/// a tone in Hz for some ms, 0Hz is a rest
+type Note = (u16, u16);
+
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub enum Sound {
+    Horn,
+    Connected,
+    Disconnected,
+    /// the failsafe stopped the car
+    Failsafe,
+}
+
+impl Sound {
+    fn notes(self) -> &'static [Note] {
+        match self {
+            Sound::Horn => &[(440, 150), (0, 50), (440, 400)],
+            Sound::Connected => &[(523, 100), (659, 100), (784, 200)],
+            Sound::Disconnected => &[(784, 100), (659, 100), (523, 200)],
+            Sound::Failsafe => &[(880, 100), (0, 50), (880, 100), (0, 50), (880, 100)],
+        }
+    }
+}
+
+const REVERSE_BEEP: Note = (1000, BEEP_MS);
+
+static SOUNDS: Channel<ThreadModeRawMutex, Sound, 4> = Channel::new();
+static REVERSING: AtomicBool = AtomicBool::new(false);
+
+/// queues `sound`, dropped if a few are already waiting
+pub fn play(sound: Sound) {
+    if SOUNDS.try_send(sound).is_err() {
+        debug!("sound queue full, dropping {}", sound);
+    }
+}
+
+/// to be called with the forward speed the car drives with
+pub fn update_reversing(vy: f32) {
+    REVERSING.store(vy < REVERSING_BELOW, Ordering::Relaxed);
+}
+
+/// the speaker pin idles low while the pwm is off
+async fn play_notes(pwm: &SimplePwm<'static, PWM2>, notes: &[Note]) {
+    for &(hz, ms) in notes {
+        if hz == 0 {
+            pwm.disable();
+        } else {
+            let top = (PWM_CLOCK_HZ / hz as u32) as u16;
+            pwm.enable();
+            pwm.set_max_duty(top);
+            pwm.set_duty(0, top / 2);
+        }
+        Timer::after_millis(ms as u64).await;
+    }
+    pwm.disable();
+}
+
+#[embassy_executor::task]
+pub async fn play_sounds(pwm: PWM2, speaker: P0_00) {
+    let pwm = SimplePwm::new_1ch(pwm, speaker);
+    pwm.set_prescaler(Prescaler::Div16);
+    pwm.disable();
+    loop {
+        match with_timeout(BEEP_PERIOD, SOUNDS.receive()).await {
+            Ok(sound) => play_notes(&pwm, sound.notes()).await,
+            Err(_) if REVERSING.load(Ordering::Relaxed) => play_notes(&pwm, &[REVERSE_BEEP]).await,
+            Err(_) => {}
+        }
+    }
+}
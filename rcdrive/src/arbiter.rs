@@ -0,0 +1,215 @@
+//! Which of the connected clients drives the car
+//!
+//! Clients take the first free slot when they connect. The first one to
+//! claim control becomes the driver, everybody else watches until the driver
+//! releases control, hands it over or disconnects, or the instructor
+//! overrides with the pin from the car config.
+
+use rcproto::{ControlRequest, ControlStatus};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlError {
+    /// another client drives
+    Taken,
+    /// only the driver may do that
+    NotDriver,
+    /// no client in that slot
+    NoSuchSlot,
+    /// the pin is wrong, or the car has no instructor
+    WrongPin,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Arbiter<const N: usize> {
+    connected: [bool; N],
+    driver: Option<usize>,
+    /// the slot that last overrode with the pin
+    instructor: Option<usize>,
+    /// 0 turns overriding off
+    pub instructor_pin: u32,
+}
+
+impl<const N: usize> Default for Arbiter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Arbiter<N> {
+    pub const fn new() -> Self {
+        Arbiter {
+            connected: [false; N],
+            driver: None,
+            instructor: None,
+            instructor_pin: 0,
+        }
+    }
+
+    /// the slot of a new client, `None` with every slot taken
+    pub fn connect(&mut self) -> Option<usize> {
+        let slot = self.connected.iter().position(|c| !c)?;
+        self.connected[slot] = true;
+        Some(slot)
+    }
+
+    /// frees the slot, returns whether the client was the driver
+    pub fn disconnect(&mut self, slot: usize) -> bool {
+        if let Some(c) = self.connected.get_mut(slot) {
+            *c = false;
+        }
+        self.instructor.take_if(|i| *i == slot);
+        self.driver.take_if(|d| *d == slot).is_some()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.connected.iter().all(|c| *c)
+    }
+
+    pub fn driver(&self) -> Option<usize> {
+        self.driver
+    }
+
+    pub fn may_drive(&self, slot: usize) -> bool {
+        self.driver == Some(slot)
+    }
+
+    /// whether the client in `slot` may change the pin, which takes the
+    /// instructor once there is one
+    pub fn may_set_pin(&self, slot: usize) -> bool {
+        self.instructor_pin == 0 || self.instructor == Some(slot)
+    }
+
+    fn is_connected(&self, slot: usize) -> bool {
+        self.connected.get(slot).copied().unwrap_or(false)
+    }
+
+    /// carries out `request` of the client in `slot`, the driver may have
+    /// changed when it returns `Ok`
+    pub fn apply(&mut self, slot: usize, request: &ControlRequest) -> Result<(), ControlError> {
+        if !self.is_connected(slot) {
+            return Err(ControlError::NoSuchSlot);
+        }
+        match *request {
+            ControlRequest::Claim => match self.driver {
+                Some(d) if d != slot => return Err(ControlError::Taken),
+                _ => self.driver = Some(slot),
+            },
+            ControlRequest::Release => {
+                if !self.may_drive(slot) {
+                    return Err(ControlError::NotDriver);
+                }
+                self.driver = None;
+            }
+            ControlRequest::Handover { slot: to } => {
+                if !self.may_drive(slot) {
+                    return Err(ControlError::NotDriver);
+                }
+                if !self.is_connected(to as usize) {
+                    return Err(ControlError::NoSuchSlot);
+                }
+                self.driver = Some(to as usize);
+            }
+            ControlRequest::Override { pin } => {
+                if self.instructor_pin == 0 || pin != self.instructor_pin {
+                    return Err(ControlError::WrongPin);
+                }
+                self.driver = Some(slot);
+                self.instructor = Some(slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// the table as the client in `you` sees it
+    pub fn status(&self, you: Option<usize>) -> ControlStatus {
+        let connected = self
+            .connected
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c)
+            .fold(0, |bits, (slot, _)| bits | 1 << slot);
+        ControlStatus {
+            you: you.map(|s| s as u8),
+            driver: self.driver.map(|s| s as u8),
+            connected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_claim_drives() {
+        let mut arbiter = Arbiter::<3>::new();
+        let a = arbiter.connect().unwrap();
+        let b = arbiter.connect().unwrap();
+        assert_eq!((a, b), (0, 1));
+
+        assert_eq!(arbiter.apply(b, &ControlRequest::Claim), Ok(()));
+        assert_eq!(
+            arbiter.apply(a, &ControlRequest::Claim),
+            Err(ControlError::Taken)
+        );
+        assert!(arbiter.may_drive(b) && !arbiter.may_drive(a));
+        assert_eq!(
+            arbiter.apply(a, &ControlRequest::Release),
+            Err(ControlError::NotDriver)
+        );
+
+        // the driver leaves, the slot is reused
+        assert!(arbiter.disconnect(b));
+        assert_eq!(arbiter.driver(), None);
+        assert_eq!(arbiter.connect(), Some(1));
+        assert_eq!(arbiter.apply(a, &ControlRequest::Claim), Ok(()));
+        assert_eq!(arbiter.status(Some(a)).encode(), [0, 0, 0b011]);
+    }
+
+    #[test]
+    fn handover_and_override() {
+        let mut arbiter = Arbiter::<3>::new();
+        let student = arbiter.connect().unwrap();
+        let other = arbiter.connect().unwrap();
+        let instructor = arbiter.connect().unwrap();
+        assert!(arbiter.is_full());
+        assert_eq!(arbiter.connect(), None);
+        arbiter.apply(student, &ControlRequest::Claim).unwrap();
+
+        let to_gone = ControlRequest::Handover { slot: 7 };
+        assert_eq!(
+            arbiter.apply(student, &to_gone),
+            Err(ControlError::NoSuchSlot)
+        );
+        let to_other = ControlRequest::Handover { slot: other as u8 };
+        assert_eq!(arbiter.apply(student, &to_other), Ok(()));
+        assert!(arbiter.may_drive(other));
+
+        // no instructor without a pin
+        let pin = ControlRequest::Override { pin: 0 };
+        assert_eq!(arbiter.apply(instructor, &pin), Err(ControlError::WrongPin));
+        assert!(arbiter.may_set_pin(student));
+        arbiter.instructor_pin = 1234;
+        let wrong = ControlRequest::Override { pin: 4321 };
+        assert_eq!(
+            arbiter.apply(instructor, &wrong),
+            Err(ControlError::WrongPin)
+        );
+        let right = ControlRequest::Override { pin: 1234 };
+        assert_eq!(arbiter.apply(instructor, &right), Ok(()));
+        assert!(arbiter.may_drive(instructor) && !arbiter.may_drive(other));
+        assert!(!arbiter.disconnect(other));
+
+        // only the instructor changes the pin, until they leave
+        assert!(arbiter.may_set_pin(instructor) && !arbiter.may_set_pin(student));
+        let to_student = ControlRequest::Handover {
+            slot: student as u8,
+        };
+        arbiter.apply(instructor, &to_student).unwrap();
+        assert!(arbiter.may_set_pin(instructor));
+        assert!(!arbiter.disconnect(instructor));
+        assert!(!arbiter.may_set_pin(instructor) && !arbiter.may_set_pin(student));
+    }
+}
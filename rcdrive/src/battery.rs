@@ -0,0 +1,189 @@
+//! Pack voltage and the speed limit of a flat battery
+//!
+//! A LiPo sags under load and recovers at rest, so the thresholds of the
+//! [`BatteryConfig`] apply to a moving average, and a state is only left
+//! once the voltage is back above its threshold by [`HYSTERESIS_MV`]. A cut
+//! battery stays cut until it reads above the low threshold again, e.g.
+//! after a swap.
+//!
+//! A reading no pack could give, next to nothing or well above full, means
+//! there is no divider on the pin, or no pack on the divider. That is
+//! [`BatteryState::NoSensor`], which doesn't limit the speed.
+
+use rcproto::BatteryConfig;
+
+/// weight of a new sample in the moving average
+const SMOOTHING: f32 = 0.1;
+/// how far above a threshold the voltage has to recover
+pub const HYSTERESIS_MV: f32 = 200.0;
+/// fraction of full speed left while the battery is low
+pub const LOW_SPEED: f32 = 0.5;
+/// how far above `full_mv` a pack still reads plausibly
+pub const ABOVE_FULL_MV: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BatteryState {
+    #[default]
+    Ok,
+    /// below `low_mv`, the speed is limited
+    Low,
+    /// below `cutoff_mv`, the motors are off
+    Cutoff,
+    /// the last reading was implausible
+    NoSensor,
+}
+
+impl BatteryState {
+    /// fraction of full speed the car may drive with
+    pub fn speed_limit(self) -> f32 {
+        match self {
+            BatteryState::Ok | BatteryState::NoSensor => 1.0,
+            BatteryState::Low => LOW_SPEED,
+            BatteryState::Cutoff => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    /// `None` until the first sample
+    filtered_mv: Option<f32>,
+    state: BatteryState,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        BatteryMonitor {
+            config,
+            filtered_mv: None,
+            state: BatteryState::Ok,
+        }
+    }
+
+    pub fn state(&self) -> BatteryState {
+        self.state
+    }
+
+    /// filtered pack voltage in mV
+    pub fn pack_mv(&self) -> Option<f32> {
+        self.filtered_mv
+    }
+
+    /// rough charge in percent, linear between the cutoff and full voltage,
+    /// `None` without a plausible reading
+    pub fn level(&self) -> Option<u8> {
+        let mv = self.filtered_mv?;
+        let cutoff = self.config.cutoff_mv as f32;
+        let full = self.config.full_mv as f32;
+        Some(((mv - cutoff) / (full - cutoff) * 100.0).clamp(0.0, 100.0) as u8)
+    }
+
+    /// whether a pack could read `mv`, a flat one still reads above half
+    /// the cutoff
+    fn plausible(&self, mv: f32) -> bool {
+        let lowest = self.config.cutoff_mv as f32 / 2.0;
+        let highest = self.config.full_mv as f32 + ABOVE_FULL_MV;
+        (lowest..=highest).contains(&mv)
+    }
+
+    /// feeds a sample of the analog pin, in mV
+    pub fn update(&mut self, pin_mv: f32) -> BatteryState {
+        let mv = pin_mv * self.config.divider;
+        if !self.plausible(mv) {
+            self.filtered_mv = None;
+            self.state = BatteryState::NoSensor;
+            return self.state;
+        }
+        let filtered = match self.filtered_mv {
+            Some(f) => f + SMOOTHING * (mv - f),
+            None => mv,
+        };
+        self.filtered_mv = Some(filtered);
+
+        let low = self.config.low_mv as f32;
+        let recovered = filtered >= low + HYSTERESIS_MV;
+        self.state = match self.state {
+            _ if filtered < self.config.cutoff_mv as f32 => BatteryState::Cutoff,
+            BatteryState::Ok | BatteryState::NoSensor if filtered < low => BatteryState::Low,
+            BatteryState::NoSensor => BatteryState::Ok,
+            BatteryState::Low | BatteryState::Cutoff if recovered => BatteryState::Ok,
+            state => state,
+        };
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a 1:3 divider
+    fn config() -> BatteryConfig {
+        BatteryConfig {
+            divider: 3.0,
+            ..BatteryConfig::default()
+        }
+    }
+
+    /// pin voltage of a pack voltage with the divider of [`config`]
+    fn pin(pack_mv: f32) -> f32 {
+        pack_mv / config().divider
+    }
+
+    fn settled(pack_mv: f32) -> BatteryMonitor {
+        let mut monitor = BatteryMonitor::new(config());
+        monitor.update(pin(pack_mv));
+        monitor
+    }
+
+    #[test]
+    fn first_sample_seeds_the_average() {
+        let mut monitor = settled(8100.0);
+        assert!((monitor.pack_mv().unwrap() - 8100.0).abs() < 1.0);
+
+        // a single dip under load doesn't cut the motors
+        assert_eq!(monitor.update(pin(6000.0)), BatteryState::Ok);
+        assert!(monitor.pack_mv().unwrap() > 7800.0);
+    }
+
+    #[test]
+    fn limits_then_cuts_with_hysteresis() {
+        let mut monitor = settled(7100.0);
+        let mut feed =
+            |pack_mv: f32, n: usize| (0..n).map(|_| monitor.update(pin(pack_mv))).last().unwrap();
+        assert_eq!(feed(6900.0, 50), BatteryState::Low);
+        // recovering at rest isn't enough to lift the limit
+        assert_eq!(feed(7100.0, 50), BatteryState::Low);
+        assert_eq!(feed(6500.0, 50), BatteryState::Cutoff);
+        assert_eq!(feed(7100.0, 50), BatteryState::Cutoff);
+        // a fresh pack
+        assert_eq!(feed(8300.0, 50), BatteryState::Ok);
+    }
+
+    #[test]
+    fn level_spans_cutoff_to_full() {
+        assert_eq!(BatteryMonitor::new(config()).level(), None);
+        assert_eq!(settled(6000.0).level(), Some(0));
+        assert_eq!(settled(7500.0).level(), Some(50));
+        assert_eq!(settled(9000.0).level(), Some(100));
+        assert_eq!(BatteryState::Cutoff.speed_limit(), 0.0);
+    }
+
+    #[test]
+    fn implausible_reading_is_no_sensor() {
+        // a pin without a divider, or a divider without a pack
+        assert_eq!(settled(30.0).state(), BatteryState::NoSensor);
+        assert_eq!(settled(10_800.0).state(), BatteryState::NoSensor);
+        assert_eq!(settled(30.0).level(), None);
+        assert_eq!(BatteryState::NoSensor.speed_limit(), 1.0);
+
+        // a pack plugged in later is picked up at once
+        let mut monitor = settled(0.0);
+        assert_eq!(monitor.update(pin(8000.0)), BatteryState::Ok);
+        assert_eq!(monitor.update(pin(0.0)), BatteryState::NoSensor);
+        assert_eq!(monitor.update(pin(6900.0)), BatteryState::Low);
+    }
+}
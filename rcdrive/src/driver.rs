@@ -0,0 +1,138 @@
+//! Motor drivers the wheel outputs can be written to
+//!
+//! Every driver takes the outputs of [`crate::WheelMan`]: a servo angle in
+//! degrees per channel, with 90 as the neutral of a continuous rotation servo.
+//! Drivers for other hardware translate that into their own units, so the
+//! calibration in the car config works the same for all of them.
+
+mod hbridge;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod pca9685;
+pub mod supervisor;
+mod wukong;
+
+pub use hbridge::{HBridge, Pwm};
+pub use pca9685::Pca9685;
+pub use supervisor::Supervisor;
+pub use wukong::Wukong;
+
+use crate::MotorWriteBufs;
+
+/// output that leaves a motor standing still
+pub const NEUTRAL: u8 = 90;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriverError<E> {
+    /// the bus to the motor board failed
+    Bus(E),
+    /// the driver has no such channel
+    Channel(u8),
+    /// the motor board didn't answer in time
+    Timeout,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait MotorDriver {
+    type Error;
+
+    /// prepares the hardware, called once before the first output
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// sets the output of one channel
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Self::Error>;
+
+    /// brings a driver back after repeated errors, e.g. by clearing its bus
+    async fn recover(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// sets every channel of a frame, stopping at the first error
+    async fn write(&mut self, bufs: &MotorWriteBufs) -> Result<(), Self::Error> {
+        for [channel, output] in bufs.iter().copied() {
+            self.set(channel, output).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use rcproto::CarConfig;
+
+    use super::mock::{MockI2c, MockMotors, MockPwm};
+    use super::*;
+    use crate::{kinematics::Mecanum, Kinematics, WheelMan};
+
+    /// forward at half speed, as every backend receives it
+    fn forward() -> MotorWriteBufs {
+        let mut config = CarConfig::default();
+        config.motor_channels = [0, 1, 2, 3];
+        WheelMan::new(&config).bufs(Mecanum.outputs(0.0, 0.5, 0.0))
+    }
+
+    #[test]
+    fn wukong_writes_a_frame_per_motor() {
+        let mut motors = Wukong::new(MockI2c::default(), 0x10);
+        block_on(motors.write(&forward())).unwrap();
+        let writes = motors.release().writes;
+        // front left, front right, back left, back right
+        assert_eq!(
+            writes,
+            [
+                (0x10, vec![0, 135, 0, 0]),
+                (0x10, vec![2, 45, 0, 0]),
+                (0x10, vec![1, 135, 0, 0]),
+                (0x10, vec![3, 45, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn pca9685_sets_up_50hz_and_pulse_widths() {
+        let mut motors = Pca9685::new(MockI2c::default(), 0x40);
+        block_on(motors.init()).unwrap();
+        block_on(motors.set(3, NEUTRAL)).unwrap();
+        block_on(motors.set(0, 180)).unwrap();
+        assert_eq!(
+            block_on(motors.set(16, NEUTRAL)),
+            Err(DriverError::Channel(16))
+        );
+        let writes = motors.release().writes;
+        assert!(writes.contains(&(0x40, vec![0xfe, 121])));
+        // 1.5ms and 2ms of a 20ms period, in 4096 steps
+        assert_eq!(
+            writes[writes.len() - 2],
+            (0x40, vec![0x06 + 12, 0, 0, 51, 1])
+        );
+        assert_eq!(writes[writes.len() - 1], (0x40, vec![0x06, 0, 0, 153, 1]));
+    }
+
+    #[test]
+    fn hbridge_drives_one_input_per_direction() {
+        let mut motors = HBridge::new([MockPwm::new(1000), MockPwm::new(1000)]);
+        block_on(motors.write(&forward())).unwrap();
+        assert_eq!(
+            block_on(motors.set(4, NEUTRAL)),
+            Err(DriverError::Channel(4))
+        );
+        let [left, right] = motors.release();
+        assert_eq!(left.duty, [500, 0, 500, 0]);
+        // the right side is mirrored, so it runs on the reverse inputs
+        assert_eq!(right.duty, [0, 500, 0, 500]);
+    }
+
+    #[test]
+    fn mock_motors_record_the_last_output() {
+        let mut motors = MockMotors::default();
+        block_on(motors.write(&forward())).unwrap();
+        block_on(motors.set(2, NEUTRAL)).unwrap();
+        assert_eq!(motors.outputs[&0], 135);
+        assert_eq!(motors.outputs[&2], NEUTRAL);
+        assert_eq!(motors.writes, 5);
+    }
+}
@@ -0,0 +1,54 @@
+//! DRV8833 or TB6612 h-bridges, driven by pwm on both inputs of a motor
+//!
+//! A motor turns forward with pwm on its first input and the second low, and
+//! backwards the other way around. For a TB6612, tie PWMx high and wire
+//! xIN1/xIN2 like the DRV8833 inputs.
+
+use core::convert::Infallible;
+
+use super::{DriverError, MotorDriver, NEUTRAL};
+
+/// a pwm peripheral with four outputs, like the one of the nrf52
+pub trait Pwm {
+    fn max_duty(&self) -> u16;
+    /// `duty` of `max_duty` is fully on
+    fn set_duty(&mut self, output: usize, duty: u16);
+}
+
+/// up to four motors, two on each pwm peripheral
+pub struct HBridge<P> {
+    pwm: [P; 2],
+}
+
+impl<P> HBridge<P> {
+    pub fn new(pwm: [P; 2]) -> Self {
+        HBridge { pwm }
+    }
+
+    pub fn release(self) -> [P; 2] {
+        self.pwm
+    }
+}
+
+impl<P: Pwm> MotorDriver for HBridge<P> {
+    type Error = DriverError<Infallible>;
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Self::Error> {
+        let pwm = self
+            .pwm
+            .get_mut(channel as usize / 2)
+            .ok_or(DriverError::Channel(channel))?;
+        let max = pwm.max_duty() as u32;
+        let speed = output.abs_diff(NEUTRAL) as u32;
+        let duty = (speed * max / NEUTRAL as u32).min(max) as u16;
+        let (forward, reverse) = if output >= NEUTRAL {
+            (duty, 0)
+        } else {
+            (0, duty)
+        };
+        let first = 2 * (channel as usize % 2);
+        pwm.set_duty(first, forward);
+        pwm.set_duty(first + 1, reverse);
+        Ok(())
+    }
+}
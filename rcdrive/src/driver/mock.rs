@@ -0,0 +1,95 @@
+//! Host doubles for the drivers, recording everything written to them
+
+extern crate std;
+
+use std::{collections::BTreeMap, vec::Vec};
+
+use core::convert::Infallible;
+
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+
+use super::{DriverError, MotorDriver, Pwm};
+
+/// an i2c bus that accepts every write, reads return zeros
+#[derive(Debug, Default)]
+pub struct MockI2c {
+    /// address and bytes of every write, oldest first
+    pub writes: Vec<(u8, Vec<u8>)>,
+}
+
+impl ErrorType for MockI2c {
+    type Error = Infallible;
+}
+
+impl I2c for MockI2c {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Infallible> {
+        for op in operations {
+            match op {
+                Operation::Write(bytes) => self.writes.push((address, bytes.to_vec())),
+                Operation::Read(buf) => buf.fill(0),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the four outputs of a pwm peripheral
+#[derive(Debug)]
+pub struct MockPwm {
+    pub max_duty: u16,
+    pub duty: [u16; 4],
+}
+
+impl MockPwm {
+    pub fn new(max_duty: u16) -> Self {
+        MockPwm {
+            max_duty,
+            duty: [0; 4],
+        }
+    }
+}
+
+impl Pwm for MockPwm {
+    fn max_duty(&self) -> u16 {
+        self.max_duty
+    }
+
+    fn set_duty(&mut self, output: usize, duty: u16) {
+        self.duty[output] = duty;
+    }
+}
+
+/// a driver that only remembers the last output of every channel
+#[derive(Debug, Default)]
+pub struct MockMotors {
+    pub outputs: BTreeMap<u8, u8>,
+    /// number of outputs set so far
+    pub writes: usize,
+    /// how many of the next outputs of a channel fail
+    pub fail: BTreeMap<u8, usize>,
+    /// number of bus recoveries so far
+    pub recoveries: usize,
+}
+
+impl MotorDriver for MockMotors {
+    type Error = DriverError<()>;
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Self::Error> {
+        if let Some(n @ 1..) = self.fail.get_mut(&channel) {
+            *n -= 1;
+            return Err(DriverError::Bus(()));
+        }
+        self.outputs.insert(channel, output);
+        self.writes += 1;
+        Ok(())
+    }
+
+    async fn recover(&mut self) -> Result<(), Self::Error> {
+        self.recoveries += 1;
+        Ok(())
+    }
+}
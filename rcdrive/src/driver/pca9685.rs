@@ -0,0 +1,66 @@
+//! PCA9685 16 channel pwm board, driving servos or escs at 50Hz
+
+use embedded_hal_async::i2c::I2c;
+
+use super::{DriverError, MotorDriver};
+
+const MODE1: u8 = 0x00;
+const LED0_ON_L: u8 = 0x06;
+const PRESCALE: u8 = 0xfe;
+const MODE1_SLEEP: u8 = 0x10;
+const MODE1_AUTO_INCREMENT: u8 = 0x20;
+/// 25MHz / (4096 * 50Hz) - 1
+const PRESCALE_50HZ: u8 = 121;
+const PERIOD_US: u32 = 20_000;
+const CHANNELS: u8 = 16;
+
+/// pulse width of a servo angle, 1ms at 0 to 2ms at 180 degrees
+fn pulse_us(angle: u8) -> u32 {
+    1000 + angle.min(180) as u32 * 1000 / 180
+}
+
+pub struct Pca9685<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I> Pca9685<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Pca9685 { i2c, address }
+    }
+
+    pub fn release(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I: I2c> Pca9685<I> {
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DriverError<I::Error>> {
+        self.i2c
+            .write(self.address, bytes)
+            .await
+            .map_err(DriverError::Bus)
+    }
+}
+
+impl<I: I2c> MotorDriver for Pca9685<I> {
+    type Error = DriverError<I::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        // the prescaler can only be changed while asleep
+        self.write(&[MODE1, MODE1_SLEEP]).await?;
+        self.write(&[PRESCALE, PRESCALE_50HZ]).await?;
+        self.write(&[MODE1, MODE1_AUTO_INCREMENT]).await
+    }
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Self::Error> {
+        if channel >= CHANNELS {
+            return Err(DriverError::Channel(channel));
+        }
+        // on at 0, off after the pulse
+        let off = (pulse_us(output) * 4096 / PERIOD_US) as u16;
+        let [off_l, off_h] = off.to_le_bytes();
+        self.write(&[LED0_ON_L + 4 * channel, 0, 0, off_l, off_h])
+            .await
+    }
+}
@@ -0,0 +1,121 @@
+//! Retries and error bookkeeping around a [`MotorDriver`]
+
+use super::MotorDriver;
+use crate::MotorWriteBufs;
+
+/// tries per output before it counts as failed
+pub const ATTEMPTS: usize = 3;
+/// failed frames in a row between attempts to recover the bus
+pub const RECOVER_AFTER: u8 = 3;
+/// failed frames in a row before the motor board counts as lost
+pub const LOST_AFTER: u8 = 10;
+
+#[derive(Clone, Debug, Default)]
+pub struct Supervisor {
+    /// failed outputs per motor, in the order of [`MotorWriteBufs`]
+    errors: [u16; 4],
+    failed_frames: u8,
+}
+
+impl Supervisor {
+    /// writes a frame, retrying every output up to [`ATTEMPTS`] times
+    ///
+    /// the remaining outputs are still written when one fails, the error is
+    /// that of the last failed output
+    pub async fn write<D: MotorDriver>(
+        &mut self,
+        driver: &mut D,
+        bufs: &MotorWriteBufs,
+    ) -> Result<(), D::Error> {
+        let mut result = Ok(());
+        for (i, [channel, output]) in bufs.iter().copied().enumerate() {
+            let mut attempt = driver.set(channel, output).await;
+            for _ in 1..ATTEMPTS {
+                if attempt.is_ok() {
+                    break;
+                }
+                attempt = driver.set(channel, output).await;
+            }
+            if let Err(e) = attempt {
+                self.errors[i] = self.errors[i].saturating_add(1);
+                result = Err(e);
+            }
+        }
+        match result {
+            Ok(()) => self.failed_frames = 0,
+            Err(_) => self.failed_frames = self.failed_frames.saturating_add(1),
+        }
+        result
+    }
+
+    pub fn errors(&self) -> [u16; 4] {
+        self.errors
+    }
+
+    /// true every [`RECOVER_AFTER`] failed frames in a row
+    pub fn needs_recovery(&self) -> bool {
+        self.failed_frames > 0 && self.failed_frames.is_multiple_of(RECOVER_AFTER)
+    }
+
+    /// the board hasn't taken a frame for [`LOST_AFTER`] tries
+    pub fn lost(&self) -> bool {
+        self.failed_frames >= LOST_AFTER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::driver::{mock::MockMotors, NEUTRAL};
+
+    const FRAME: MotorWriteBufs = [[4, NEUTRAL], [6, NEUTRAL], [5, NEUTRAL], [7, NEUTRAL]];
+
+    #[test]
+    fn retries_hide_a_glitch() {
+        let mut motors = MockMotors {
+            fail: [(6, ATTEMPTS - 1)].into(),
+            ..MockMotors::default()
+        };
+        let mut sup = Supervisor::default();
+        assert!(block_on(sup.write(&mut motors, &FRAME)).is_ok());
+        assert_eq!(sup.errors(), [0; 4]);
+        assert_eq!(motors.outputs[&6], NEUTRAL);
+    }
+
+    #[test]
+    fn counts_errors_per_motor_and_keeps_writing() {
+        let mut motors = MockMotors {
+            fail: [(5, ATTEMPTS)].into(),
+            ..MockMotors::default()
+        };
+        let mut sup = Supervisor::default();
+        assert!(block_on(sup.write(&mut motors, &FRAME)).is_err());
+        assert_eq!(sup.errors(), [0, 0, 1, 0]);
+        assert_eq!(motors.outputs[&7], NEUTRAL);
+    }
+
+    #[test]
+    fn recovers_then_gives_up() {
+        let mut motors = MockMotors {
+            fail: [(4, usize::MAX)].into(),
+            ..MockMotors::default()
+        };
+        let mut sup = Supervisor::default();
+        let mut recoveries = 0;
+        for _ in 0..LOST_AFTER - 1 {
+            assert!(block_on(sup.write(&mut motors, &FRAME)).is_err());
+            recoveries += sup.needs_recovery() as u8;
+            assert!(!sup.lost());
+        }
+        assert_eq!(recoveries, (LOST_AFTER - 1) / RECOVER_AFTER);
+        assert!(block_on(sup.write(&mut motors, &FRAME)).is_err());
+        assert!(sup.lost());
+
+        motors.fail.clear();
+        assert!(block_on(sup.write(&mut motors, &FRAME)).is_ok());
+        assert!(!sup.lost());
+        assert_eq!(sup.errors()[0], LOST_AFTER as u16);
+    }
+}
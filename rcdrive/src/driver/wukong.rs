@@ -0,0 +1,32 @@
+//! Elecfreaks Wukong expansion board for the micro:bit
+
+use embedded_hal_async::i2c::I2c;
+
+use super::{DriverError, MotorDriver};
+
+/// takes the servo angle directly, in `[channel, angle, 0, 0]` frames
+pub struct Wukong<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I> Wukong<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Wukong { i2c, address }
+    }
+
+    pub fn release(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I: I2c> MotorDriver for Wukong<I> {
+    type Error = DriverError<I::Error>;
+
+    async fn set(&mut self, channel: u8, output: u8) -> Result<(), Self::Error> {
+        self.i2c
+            .write(self.address, &[channel, output, 0, 0])
+            .await
+            .map_err(DriverError::Bus)
+    }
+}
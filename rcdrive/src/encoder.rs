@@ -0,0 +1,87 @@
+//! Wheel speeds from encoder counts, `rcar::encoder` does the counting
+
+use rcproto::EncoderKind;
+
+/// turns encoder counts into normalized wheel speeds
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SpeedMeter {
+    /// counts per second at full motor output
+    full_speed_cps: f32,
+    last: [i32; 4],
+}
+
+impl SpeedMeter {
+    /// starts measuring from the current `counts`
+    pub fn new(full_speed_cps: f32, counts: [i32; 4]) -> Self {
+        SpeedMeter {
+            full_speed_cps,
+            last: counts,
+        }
+    }
+
+    /// speed of every wheel with an encoder since the last call
+    ///
+    /// hall encoders can't tell the direction, it is taken from `direction`
+    pub fn measure(
+        &mut self,
+        counts: [i32; 4],
+        kinds: [EncoderKind; 4],
+        dt: f32,
+        direction: &[f32; 4],
+    ) -> [Option<f32>; 4] {
+        core::array::from_fn(|i| {
+            let delta = counts[i].wrapping_sub(self.last[i]) as f32;
+            self.last[i] = counts[i];
+            if dt <= 0.0 {
+                return None;
+            }
+            let speed = delta / dt / self.full_speed_cps;
+            match kinds[i] {
+                EncoderKind::None => None,
+                EncoderKind::Quadrature => Some(speed),
+                EncoderKind::Hall if direction[i] < 0.0 => Some(-speed),
+                EncoderKind::Hall => Some(speed),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [EncoderKind; 4] = [
+        EncoderKind::Quadrature,
+        EncoderKind::Hall,
+        EncoderKind::Hall,
+        EncoderKind::None,
+    ];
+
+    #[test]
+    fn speed_is_relative_to_full_output() {
+        let mut meter = SpeedMeter::new(1000.0, [100, 0, 0, 0]);
+        let forward = [1.0; 4];
+        let speeds = meter.measure([110, 20, 5, 50], KINDS, 0.02, &forward);
+        assert_eq!(speeds, [Some(0.5), Some(1.0), Some(0.25), None]);
+        // counts go on from the last call
+        let speeds = meter.measure([110, 20, 5, 50], KINDS, 0.02, &forward);
+        assert_eq!(speeds, [Some(0.0), Some(0.0), Some(0.0), None]);
+    }
+
+    #[test]
+    fn direction_comes_from_the_count_or_the_command() {
+        let mut meter = SpeedMeter::new(1000.0, [0; 4]);
+        let reverse = [-1.0; 4];
+        let speeds = meter.measure([-10, 10, 10, 10], KINDS, 0.02, &reverse);
+        assert_eq!(speeds, [Some(-0.5), Some(-0.5), Some(-0.5), None]);
+    }
+
+    #[test]
+    fn counts_wrap() {
+        let mut meter = SpeedMeter::new(2.0, [i32::MAX; 4]);
+        let speeds = meter.measure([i32::MIN; 4], KINDS, 0.5, &[1.0; 4]);
+        assert_eq!(speeds[0], Some(1.0));
+        assert_eq!(meter.measure([0; 4], KINDS, 0.0, &[1.0; 4]), [None; 4]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Emergency stop latch
+//!
+//! Once triggered, velocity commands are ignored until the stop is re-armed.
+//! Re-arming needs the last command of the client to be centered, so the car
+//! doesn't take off with a joystick that is still pushed.
+
+use rcproto::VelocityCommand;
+
+/// commands closer to zero than this on every axis count as centered
+pub const CENTER_DEADBAND: f32 = 0.05;
+
+pub fn centered(cmd: &VelocityCommand) -> bool {
+    cmd.to_array().iter().all(|v| v.abs() < CENTER_DEADBAND)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RearmError {
+    /// the car isn't stopped
+    NotStopped,
+    /// the last command still moves the car
+    NotCentered,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EStopLatch {
+    stopped: bool,
+    last_centered: bool,
+}
+
+impl Default for EStopLatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EStopLatch {
+    pub const fn new() -> Self {
+        EStopLatch {
+            stopped: false,
+            last_centered: true,
+        }
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// latches the stop, returns false if it already was
+    pub fn trigger(&mut self) -> bool {
+        !core::mem::replace(&mut self.stopped, true)
+    }
+
+    pub fn rearm(&mut self) -> Result<(), RearmError> {
+        if !self.stopped {
+            return Err(RearmError::NotStopped);
+        }
+        if !self.last_centered {
+            return Err(RearmError::NotCentered);
+        }
+        self.stopped = false;
+        Ok(())
+    }
+
+    /// notes a command of the client, returns whether it may drive the car
+    pub fn command(&mut self, cmd: &VelocityCommand) -> bool {
+        self.last_centered = centered(cmd);
+        !self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_commands_until_rearmed() {
+        let mut latch = EStopLatch::new();
+        let forward = VelocityCommand::new(0.0, 0.8, 0.0);
+        assert!(latch.command(&forward));
+        assert!(latch.trigger());
+        assert!(!latch.trigger());
+        assert!(!latch.command(&forward));
+
+        assert_eq!(latch.rearm(), Err(RearmError::NotCentered));
+        assert!(!latch.command(&VelocityCommand::new(0.01, -0.02, 0.0)));
+        assert_eq!(latch.rearm(), Ok(()));
+        assert!(latch.command(&forward));
+        assert_eq!(latch.rearm(), Err(RearmError::NotStopped));
+    }
+
+    #[test]
+    fn rotation_counts_too() {
+        assert!(centered(&VelocityCommand::default()));
+        assert!(!centered(&VelocityCommand::new(0.0, 0.0, -0.5)));
+    }
+}
@@ -0,0 +1,176 @@
+//! Compass heading and the world frame used by headless driving
+//!
+//! Headings are in radians, counterclockwise positive, wrapped to -PI..=PI.
+//! The trig goes through `micromath` explicitly, so host tests run the same
+//! approximations as the car.
+
+use core::f32::consts::PI;
+
+use micromath::F32Ext;
+
+/// wraps an angle to -PI..=PI
+pub fn wrap(angle: f32) -> f32 {
+    let mut a = angle % (2.0 * PI);
+    if a > PI {
+        a -= 2.0 * PI;
+    } else if a < -PI {
+        a += 2.0 * PI;
+    }
+    a
+}
+
+/// rotates a world frame `(x, y)` into the frame of a car facing `heading`
+pub fn world_to_body(x: f32, y: f32, heading: f32) -> (f32, f32) {
+    let (sin, cos) = (F32Ext::sin(heading), F32Ext::cos(heading));
+    (x * cos + y * sin, -x * sin + y * cos)
+}
+
+/// rotates `(x, y)` in the frame of a car facing `heading` into the world frame
+pub fn body_to_world(x: f32, y: f32, heading: f32) -> (f32, f32) {
+    world_to_body(x, y, -heading)
+}
+
+/// the heading of a level magnetometer reading, relative to magnetic north
+///
+/// the field turns clockwise in the sensor frame when the car turns
+/// counterclockwise, hence the sign
+pub fn mag_heading(x: f32, y: f32) -> f32 {
+    -F32Ext::atan2(y, x)
+}
+
+/// removes the hard iron offset found by a [`HardIronCapture`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HardIron {
+    pub offset: [f32; 2],
+}
+
+impl HardIron {
+    pub fn correct(&self, x: f32, y: f32) -> (f32, f32) {
+        (x - self.offset[0], y - self.offset[1])
+    }
+}
+
+/// the field seen while the car spins in place
+///
+/// The offset is the middle of the extremes. Readings also mark the eighth of
+/// a turn they lie in around the middle found so far, a calibration only
+/// counts once the car went all the way round. The middle settles during the
+/// first turn, so that takes a bit more than one.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HardIronCapture {
+    min: [f32; 2],
+    max: [f32; 2],
+    /// one bit per eighth of a turn
+    seen: u8,
+}
+
+impl Default for HardIronCapture {
+    fn default() -> Self {
+        HardIronCapture {
+            min: [f32::MAX; 2],
+            max: [f32::MIN; 2],
+            seen: 0,
+        }
+    }
+}
+
+impl HardIronCapture {
+    fn center(&self) -> [f32; 2] {
+        [0, 1].map(|i| (self.min[i] + self.max[i]) / 2.0)
+    }
+
+    pub fn add(&mut self, x: f32, y: f32) {
+        for (i, v) in [x, y].into_iter().enumerate() {
+            self.min[i] = self.min[i].min(v);
+            self.max[i] = self.max[i].max(v);
+        }
+        let [cx, cy] = self.center();
+        let angle = F32Ext::atan2(y - cy, x - cx) + PI;
+        let eighth = ((angle / (PI / 4.0)) as u32).min(7);
+        self.seen |= 1 << eighth;
+    }
+
+    /// the offset, or `None` unless the readings went all the way round and
+    /// spread at least `min_span` on both axes
+    pub fn finish(&self, min_span: f32) -> Option<HardIron> {
+        let spread = (0..2).all(|i| self.max[i] - self.min[i] >= min_span);
+        (spread && self.seen == u8::MAX).then(|| HardIron {
+            offset: self.center(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+    }
+
+    #[test]
+    fn wrap_stays_in_range() {
+        assert!((wrap(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-5);
+        assert!((wrap(-3.0 * PI / 2.0) - PI / 2.0).abs() < 1e-5);
+        assert_eq!(wrap(0.5), 0.5);
+    }
+
+    #[test]
+    fn forward_in_the_world_is_right_for_a_car_facing_left() {
+        assert!(close(world_to_body(0.0, 1.0, PI / 2.0), (1.0, 0.0)));
+        assert!(close(world_to_body(1.0, 0.0, PI / 2.0), (0.0, -1.0)));
+        assert!(close(world_to_body(0.3, 0.4, 0.0), (0.3, 0.4)));
+    }
+
+    #[test]
+    fn body_to_world_undoes_world_to_body() {
+        let (x, y) = world_to_body(0.3, -0.7, 1.2);
+        assert!(close(body_to_world(x, y, 1.2), (0.3, -0.7)));
+    }
+
+    #[test]
+    fn turning_left_increases_heading() {
+        // field along +x, then the car turns left and it appears towards -y
+        let before = mag_heading(1.0, 0.0);
+        let after = mag_heading(0.0, -1.0);
+        assert!((wrap(after - before) - PI / 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn hard_iron_centers_readings() {
+        let hi = HardIron {
+            offset: [20.0, 5.0],
+        };
+        assert_eq!(hi.correct(30.0, 15.0), (10.0, 10.0));
+    }
+
+    /// readings of a car turning from `from` to `to`, with the field offset by `(40, -25)`
+    fn spin(from: f32, to: f32) -> HardIronCapture {
+        let mut capture = HardIronCapture::default();
+        for i in 0..=120 {
+            let a = from + (to - from) * i as f32 / 120.0;
+            capture.add(
+                40.0 + 200.0 * F32Ext::cos(a),
+                -25.0 + 200.0 * F32Ext::sin(a),
+            );
+        }
+        capture
+    }
+
+    #[test]
+    fn full_turn_finds_the_offset() {
+        let hi = spin(0.0, 3.0 * PI).finish(100.0).unwrap();
+        assert!(close(hi.correct(0.0, 0.0), (-40.0, 25.0)));
+        // the other way round works as well
+        assert!(spin(PI, -2.0 * PI).finish(100.0).is_some());
+    }
+
+    #[test]
+    fn partial_turn_or_weak_field_is_no_calibration() {
+        assert_eq!(spin(0.0, PI / 2.0).finish(100.0), None);
+        assert_eq!(spin(0.0, PI).finish(100.0), None);
+        assert_eq!(spin(0.0, 3.0 * PI).finish(500.0), None);
+    }
+}
@@ -0,0 +1,201 @@
+//! Body velocity to actuator outputs, one model per chassis
+//!
+//! Every model fills the four slots of a [`WheelSpeed`], which
+//! [`crate::WheelMan`] then maps onto the motor channels of the config.
+//! Like the mecanum wheels, the motors on the right side are mirrored, so
+//! they turn backwards to drive the car forward.
+
+use rcproto::KinematicsModel;
+
+use crate::WheelSpeed;
+
+pub trait Kinematics {
+    /// actuator outputs for a normalized body velocity
+    ///
+    /// `vx` strafes to the right, `vy` drives forward and a positive `wz`
+    /// turns counterclockwise
+    fn outputs(&self, vx: f32, vy: f32, wz: f32) -> WheelSpeed;
+
+    /// normalized body velocity `[vx, vy, wz]` the wheels move the car with,
+    /// the inverse of [`Kinematics::outputs`]
+    fn velocity(&self, wheels: &WheelSpeed) -> [f32; 3];
+}
+
+/// the kinematics selected in the car config
+pub fn model(model: KinematicsModel) -> &'static dyn Kinematics {
+    match model {
+        KinematicsModel::Mecanum => &Mecanum,
+        KinematicsModel::Differential => &Differential,
+        KinematicsModel::SkidSteer => &SkidSteer,
+        KinematicsModel::Ackermann => &Ackermann,
+    }
+}
+
+/// four mecanum wheels, the only model that can strafe
+pub struct Mecanum;
+
+impl Kinematics for Mecanum {
+    fn outputs(&self, vx: f32, vy: f32, wz: f32) -> WheelSpeed {
+        WheelSpeed::trans_rotate(vx, vy, wz)
+    }
+
+    fn velocity(&self, w: &WheelSpeed) -> [f32; 3] {
+        // least squares fit of drive_x, drive_y and drive_z, the rollers
+        // slip away whatever the four wheels disagree on
+        let (fl, bl, fr, br) = (w.front_left, w.back_left, w.front_right, w.back_right);
+        [
+            (fl - bl + fr - br) / 4.0,
+            (fl + bl - fr - br) / 4.0,
+            -(fl + bl + fr + br) / 4.0,
+        ]
+    }
+}
+
+/// speed of the left and right side, `vx` is ignored
+fn sides(vy: f32, wz: f32) -> (f32, f32) {
+    let left = vy - wz;
+    let right = vy + wz;
+    let max = left.abs().max(right.abs());
+    if max > 1.0 {
+        (left / max, right / max)
+    } else {
+        (left, right)
+    }
+}
+
+/// body velocity of a car driven by its left and right side
+fn from_sides(left: f32, right: f32) -> [f32; 3] {
+    [0.0, (left + right) / 2.0, (right - left) / 2.0]
+}
+
+/// two driven wheels on the front slots, the back slots stay at neutral
+pub struct Differential;
+
+impl Kinematics for Differential {
+    fn outputs(&self, _vx: f32, vy: f32, wz: f32) -> WheelSpeed {
+        let (left, right) = sides(vy, wz);
+        WheelSpeed {
+            front_left: left,
+            front_right: -right,
+            ..WheelSpeed::default()
+        }
+    }
+
+    fn velocity(&self, w: &WheelSpeed) -> [f32; 3] {
+        from_sides(w.front_left, -w.front_right)
+    }
+}
+
+/// four fixed wheels, both wheels of a side get the same speed
+pub struct SkidSteer;
+
+impl Kinematics for SkidSteer {
+    fn outputs(&self, _vx: f32, vy: f32, wz: f32) -> WheelSpeed {
+        let (left, right) = sides(vy, wz);
+        WheelSpeed {
+            front_left: left,
+            back_left: left,
+            front_right: -right,
+            back_right: -right,
+        }
+    }
+
+    fn velocity(&self, w: &WheelSpeed) -> [f32; 3] {
+        let left = (w.front_left + w.back_left) / 2.0;
+        let right = -(w.front_right + w.back_right) / 2.0;
+        from_sides(left, right)
+    }
+}
+
+/// drive motor on the front left slot, steering servo on the front right slot
+///
+/// `wz` sets the steering angle. It flips while reversing, so the car
+/// still turns the commanded way. The car can't turn on the spot.
+pub struct Ackermann;
+
+impl Kinematics for Ackermann {
+    fn outputs(&self, _vx: f32, vy: f32, wz: f32) -> WheelSpeed {
+        let steer = if vy < 0.0 { -wz } else { wz };
+        WheelSpeed {
+            front_left: vy.clamp(-1.0, 1.0),
+            front_right: steer.clamp(-1.0, 1.0),
+            ..WheelSpeed::default()
+        }
+    }
+
+    /// the turn rate grows with the speed, for small steering angles
+    fn velocity(&self, w: &WheelSpeed) -> [f32; 3] {
+        let vy = w.front_left;
+        [0.0, vy, vy * w.front_right]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mecanum_matches_trans_rotate() {
+        let m = model(KinematicsModel::Mecanum);
+        let (x, y, z) = (0.3, -0.5, 0.2);
+        assert_eq!(m.outputs(x, y, z), WheelSpeed::trans_rotate(x, y, z));
+    }
+
+    #[test]
+    fn tank_models_turn_like_mecanum() {
+        // without strafing the mecanum wheels act like a skid steer car
+        let mecanum = Mecanum.outputs(0.0, 0.4, 0.0);
+        assert_eq!(SkidSteer.outputs(0.7, 0.4, 0.0), mecanum);
+
+        let spin = SkidSteer.outputs(0.0, 0.0, 0.5);
+        assert_eq!(spin, WheelSpeed::drive_z(0.5));
+    }
+
+    #[test]
+    fn sides_are_normalized() {
+        let out = Differential.outputs(0.0, 1.0, 1.0);
+        assert_eq!(out.front_left, 0.0);
+        assert_eq!(out.front_right, -1.0);
+        assert_eq!(out.back_left, 0.0);
+        assert_eq!(out.back_right, 0.0);
+    }
+
+    #[test]
+    fn velocity_inverts_outputs() {
+        let (x, y, z) = (0.2, -0.3, 0.1);
+        let [vx, vy, wz] = Mecanum.velocity(&Mecanum.outputs(x, y, z));
+        // trans_rotate scales the rotation down while translating, with the
+        // approximated magnitude of micromath
+        let z = z / (2.0 - (x * x + y * y).sqrt());
+        assert!((vx - x).abs() < 1e-6 && (vy - y).abs() < 1e-6);
+        assert!((wz - z).abs() < 1e-3);
+
+        let tank = [0.0, 0.5, -0.25];
+        assert_eq!(
+            SkidSteer.velocity(&SkidSteer.outputs(0.0, 0.5, -0.25)),
+            tank
+        );
+        assert_eq!(
+            Differential.velocity(&Differential.outputs(0.0, 0.5, -0.25)),
+            tank
+        );
+    }
+
+    #[test]
+    fn ackermann_turns_the_commanded_way_in_reverse() {
+        let [_, vy, wz] = Ackermann.velocity(&Ackermann.outputs(0.0, -0.5, 0.4));
+        assert_eq!(vy, -0.5);
+        assert!(wz > 0.0);
+    }
+
+    #[test]
+    fn ackermann_flips_steering_in_reverse() {
+        let forward = Ackermann.outputs(0.0, 0.5, 0.3);
+        assert_eq!(forward.front_left, 0.5);
+        assert_eq!(forward.front_right, 0.3);
+
+        let reverse = Ackermann.outputs(0.0, -0.5, 0.3);
+        assert_eq!(reverse.front_left, -0.5);
+        assert_eq!(reverse.front_right, -0.3);
+    }
+}
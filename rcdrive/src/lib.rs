@@ -0,0 +1,33 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Drive math of the car that doesn't touch any hardware
+//!
+//! Kept apart from `rcar` so it can be tested on the host, e.g.
+//! `cargo test -p rcdrive --target x86_64-unknown-linux-gnu`
+
+pub mod arbiter;
+pub mod battery;
+pub mod driver;
+pub mod encoder;
+pub mod estop;
+pub mod heading;
+pub mod kinematics;
+pub mod line;
+pub mod obstacle;
+pub mod odometry;
+pub mod pid;
+pub mod pipeline;
+pub mod profile;
+pub mod script;
+pub mod wheel;
+pub mod wheelman;
+
+pub use driver::MotorDriver;
+pub use encoder::SpeedMeter;
+pub use kinematics::Kinematics;
+pub use odometry::Odometry;
+pub use pid::Pid;
+pub use pipeline::Pipeline;
+pub use profile::MotionProfile;
+pub use wheel::WheelSpeed;
+pub use wheelman::{MotorWriteBufs, WheelMan};
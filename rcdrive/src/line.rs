@@ -0,0 +1,134 @@
+//! Steering along a line seen by an array of 2 to 5 IR sensors
+//!
+//! Readings go from 0.0 off the line to 1.0 on it, so digital and analog
+//! sensors work alike. The sensors sit evenly spaced across the front of
+//! the car, the leftmost first.
+
+use rcproto::{LineFollow, VelocityCommand};
+
+/// a reading at least this high sees the line
+const ON_LINE: f32 = 0.5;
+/// share of the forward speed given up while steering hard
+const SLOW_IN_TURNS: f32 = 0.6;
+/// turn rate while looking for a lost line
+const SEARCH_TURN: f32 = 0.4;
+/// how long the car turns towards a lost line before it gives up, in s
+const SEARCH_TIME: f32 = 1.0;
+
+/// where the line is under the sensors, from -1.0 under the leftmost to
+/// 1.0 under the rightmost, `None` if no sensor sees it
+pub fn line_position(readings: &[f32]) -> Option<f32> {
+    if readings.len() < 2 || !readings.iter().any(|r| *r >= ON_LINE) {
+        return None;
+    }
+    let step = 2.0 / (readings.len() - 1) as f32;
+    let (mut weighted, mut total) = (0.0, 0.0);
+    for (i, r) in readings.iter().enumerate() {
+        weighted += r * (i as f32 * step - 1.0);
+        total += r;
+    }
+    Some(weighted / total)
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineFollower {
+    pub params: LineFollow,
+    /// where the line was last seen
+    last_position: Option<f32>,
+    /// seconds since the line was last seen
+    lost_for: f32,
+}
+
+impl LineFollower {
+    pub fn new(params: LineFollow) -> Self {
+        LineFollower {
+            params,
+            last_position: None,
+            lost_for: 0.0,
+        }
+    }
+
+    /// forgets the line, e.g. when the car was put down somewhere else
+    pub fn reset(&mut self) {
+        self.last_position = None;
+        self.lost_for = 0.0;
+    }
+
+    /// the command for the next `dt` seconds, forward in `y` and turning in `z`
+    ///
+    /// a lost line is searched for on the side it was last seen, then the
+    /// car stops until it shows up again
+    pub fn update(&mut self, readings: &[f32], dt: f32) -> VelocityCommand {
+        let Some(position) = line_position(readings) else {
+            self.lost_for += dt;
+            return match self.last_position {
+                Some(last) if self.lost_for < SEARCH_TIME => {
+                    VelocityCommand::new(0.0, 0.0, -SEARCH_TURN * last.signum())
+                }
+                _ => VelocityCommand::default(),
+            };
+        };
+        let rate = match self.last_position {
+            Some(last) if self.lost_for == 0.0 && dt > 0.0 => (position - last) / dt,
+            _ => 0.0,
+        };
+        self.last_position = Some(position);
+        self.lost_for = 0.0;
+
+        let p = &self.params;
+        // a line to the right needs a clockwise turn
+        let steer = (p.kp * position + p.kd * rate).clamp(-1.0, 1.0);
+        let forward = p.speed * (1.0 - SLOW_IN_TURNS * steer.abs());
+        VelocityCommand::new(0.0, forward, -steer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn follower() -> LineFollower {
+        LineFollower::new(LineFollow {
+            kd: 0.0,
+            ..LineFollow::default()
+        })
+    }
+
+    #[test]
+    fn position_spans_the_array() {
+        assert_eq!(line_position(&[1.0, 0.0, 0.0, 0.0, 0.0]), Some(-1.0));
+        assert_eq!(line_position(&[0.0, 0.0, 1.0, 0.0, 0.0]), Some(0.0));
+        assert_eq!(line_position(&[0.0, 0.0, 0.0, 1.0, 1.0]), Some(0.75));
+        assert_eq!(line_position(&[1.0, 1.0]), Some(0.0));
+        assert_eq!(line_position(&[0.2, 0.1, 0.0]), None);
+        assert_eq!(line_position(&[1.0]), None);
+    }
+
+    #[test]
+    fn steers_towards_the_line() {
+        let mut f = follower();
+        let straight = f.update(&[0.0, 1.0, 0.0], 0.02);
+        assert_eq!(straight.to_array(), [0.0, 0.3, 0.0]);
+
+        let right = f.update(&[0.0, 0.0, 1.0], 0.02);
+        assert!(right.z < 0.0);
+        assert!(right.y < straight.y && right.y > 0.0);
+    }
+
+    #[test]
+    fn searches_then_gives_up() {
+        let mut f = follower();
+        f.update(&[1.0, 0.0, 0.0], 0.02);
+        let search = f.update(&[0.0; 3], 0.02);
+        assert_eq!(search.to_array(), [0.0, 0.0, SEARCH_TURN]);
+
+        for _ in 0..100 {
+            f.update(&[0.0; 3], 0.02);
+        }
+        assert_eq!(f.update(&[0.0; 3], 0.02), VelocityCommand::default());
+
+        // found again
+        assert!(f.update(&[0.0, 1.0, 0.0], 0.02).y > 0.0);
+    }
+}
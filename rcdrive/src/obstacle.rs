@@ -0,0 +1,92 @@
+//! Keeps the car from driving into what the ultrasonic sensor sees
+//!
+//! The sensor looks ahead, so only forward motion is gated. Reversing,
+//! strafing and turning away still work while something is close.
+
+/// speed of sound at room temperature, in mm per µs
+const SOUND_MM_PER_US: f32 = 0.343;
+/// echoes longer than this come from nothing in range, about 4m away
+pub const MAX_ECHO_US: u32 = 25_000;
+/// forward motion stops when an obstacle is closer than this, in mm
+pub const STOP_MM: u16 = 250;
+/// how much further away the obstacle has to be to clear the way again
+const CLEAR_MARGIN_MM: u16 = 50;
+
+/// distance to an obstacle from the round trip time of its echo, in mm
+pub fn echo_distance_mm(echo_us: u32) -> Option<u16> {
+    if echo_us > MAX_ECHO_US {
+        return None;
+    }
+    Some((echo_us as f32 * SOUND_MM_PER_US / 2.0) as u16)
+}
+
+/// body velocity `[vx, vy, wz]` without the forward motion while `blocked`
+pub fn gate([vx, vy, wz]: [f32; 3], blocked: bool) -> [f32; 3] {
+    if blocked {
+        [vx, vy.min(0.0), wz]
+    } else {
+        [vx, vy, wz]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ObstacleDetector {
+    stop_mm: u16,
+    blocked: bool,
+}
+
+impl ObstacleDetector {
+    pub fn new(stop_mm: u16) -> Self {
+        ObstacleDetector {
+            stop_mm,
+            blocked: false,
+        }
+    }
+
+    pub fn blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// feeds a measurement, `None` when nothing echoed within range
+    pub fn update(&mut self, distance_mm: Option<u16>) -> bool {
+        let limit = if self.blocked {
+            self.stop_mm.saturating_add(CLEAR_MARGIN_MM)
+        } else {
+            self.stop_mm
+        };
+        self.blocked = distance_mm.is_some_and(|d| d < limit);
+        self.blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_is_a_round_trip() {
+        // 1m there and back
+        assert_eq!(echo_distance_mm(5831), Some(1000));
+        assert_eq!(echo_distance_mm(0), Some(0));
+        assert_eq!(echo_distance_mm(MAX_ECHO_US + 1), None);
+    }
+
+    #[test]
+    fn clears_with_a_margin() {
+        let mut detector = ObstacleDetector::new(STOP_MM);
+        assert!(!detector.update(Some(STOP_MM)));
+        assert!(detector.update(Some(STOP_MM - 1)));
+        assert!(detector.update(Some(STOP_MM + 10)));
+        assert!(!detector.update(Some(STOP_MM + CLEAR_MARGIN_MM)));
+        assert!(detector.update(Some(10)));
+        assert!(!detector.update(None));
+    }
+
+    #[test]
+    fn only_forward_motion_is_gated() {
+        assert_eq!(gate([0.5, 0.8, 0.3], true), [0.5, 0.0, 0.3]);
+        assert_eq!(gate([-0.5, -0.8, 0.3], true), [-0.5, -0.8, 0.3]);
+        assert_eq!(gate([0.5, 0.8, 0.3], false), [0.5, 0.8, 0.3]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Dead reckoning of the car's pose from its wheel speeds
+//!
+//! The wheel speeds go through [`Kinematics::velocity`], so the estimate is
+//! only as good as the model and the scale below. Slip, especially of the
+//! mecanum rollers, adds up over time.
+
+use rcproto::Pose;
+
+use crate::{
+    heading::{body_to_world, wrap},
+    Kinematics, WheelSpeed,
+};
+
+pub struct Odometry {
+    pose: Pose,
+    /// ground speed of a wheel at full output, in m/s
+    full_speed: f32,
+    /// distance from the center of rotation to the wheels, in m
+    ///
+    /// half the track for tank steering, half the track plus half the
+    /// wheelbase for mecanum wheels and the wheelbase for Ackermann
+    lever: f32,
+}
+
+impl Odometry {
+    pub fn new(full_speed: f32, lever: f32) -> Self {
+        Odometry {
+            pose: Pose::default(),
+            full_speed,
+            lever,
+        }
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    pub fn reset(&mut self) {
+        self.pose = Pose::default();
+    }
+
+    /// advances the pose by `dt` seconds of driving with `wheels`
+    pub fn update(&mut self, model: &dyn Kinematics, wheels: &WheelSpeed, dt: f32) -> Pose {
+        let [vx, vy, wz] = model.velocity(wheels);
+        let turn = wz * self.full_speed / self.lever * dt;
+        // move along the heading halfway through the turn
+        let mid = self.pose.heading + turn / 2.0;
+        let (dx, dy) = body_to_world(vx * self.full_speed * dt, vy * self.full_speed * dt, mid);
+        self.pose.x += dx;
+        self.pose.y += dy;
+        self.pose.heading = wrap(self.pose.heading + turn);
+        self.pose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::*;
+    use crate::kinematics::{Mecanum, SkidSteer};
+
+    fn drive(odo: &mut Odometry, model: &dyn Kinematics, v: [f32; 3], secs: f32) -> Pose {
+        let wheels = model.outputs(v[0], v[1], v[2]);
+        for _ in 0..(secs * 50.0) as usize {
+            odo.update(model, &wheels, 0.02);
+        }
+        odo.pose()
+    }
+
+    #[test]
+    fn straight_ahead() {
+        let mut odo = Odometry::new(2.0, 0.2);
+        let pose = drive(&mut odo, &Mecanum, [0.0, 0.5, 0.0], 1.0);
+        assert!(pose.x.abs() < 1e-4);
+        assert!((pose.y - 1.0).abs() < 1e-3);
+        assert_eq!(pose.heading, 0.0);
+    }
+
+    #[test]
+    fn strafe_after_a_left_turn_goes_forward_in_the_world() {
+        let mut odo = Odometry::new(1.0, 0.25);
+        // a quarter turn at 0.5 * 1 m/s / 0.25 m = 2 rad/s
+        drive(&mut odo, &SkidSteer, [0.0, 0.0, 0.5], PI / 4.0);
+        let turned = odo.pose();
+        assert!((turned.heading - PI / 2.0).abs() < 0.03);
+
+        // facing left, strafing right drives along +y
+        drive(&mut odo, &Mecanum, [0.5, 0.0, 0.0], 1.0);
+        let pose = odo.pose();
+        assert!((pose.y - turned.y - 0.5).abs() < 0.02);
+        assert!((pose.x - turned.x).abs() < 0.02);
+    }
+
+    #[test]
+    fn reset_returns_to_origin() {
+        let mut odo = Odometry::new(1.0, 0.2);
+        drive(&mut odo, &Mecanum, [0.3, 0.3, 0.1], 0.5);
+        odo.reset();
+        assert_eq!(odo.pose(), Pose::default());
+    }
+}
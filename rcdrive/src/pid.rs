@@ -0,0 +1,71 @@
+//! PID controller closing the loop on a single wheel speed
+
+use rcproto::PidGains;
+
+/// largest correction the controller may add on top of the target
+const LIMIT: f32 = 1.0;
+
+#[derive(Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pid {
+    integral: f32,
+    last_error: f32,
+}
+
+impl Pid {
+    pub fn reset(&mut self) {
+        *self = Pid::default();
+    }
+
+    /// returns the correction to add to the feed forward speed
+    ///
+    /// the integral is frozen while the output saturates, to avoid windup
+    pub fn update(&mut self, gains: &PidGains, error: f32, dt: f32) -> f32 {
+        if dt <= 0.0 {
+            return 0.0;
+        }
+        let derivative = (error - self.last_error) / dt;
+        self.last_error = error;
+
+        let integral = self.integral + error * dt;
+        let out = gains.kp * error + gains.ki * integral + gains.kd * derivative;
+        if out.abs() < LIMIT {
+            self.integral = integral;
+        }
+        out.clamp(-LIMIT, LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gains(kp: f32, ki: f32, kd: f32) -> PidGains {
+        PidGains { kp, ki, kd }
+    }
+
+    #[test]
+    fn terms_add_up() {
+        let mut pid = Pid::default();
+        // 0.5 * 0.2 + 1.0 * 0.2 * 0.1 + 0.01 * 0.2 / 0.1
+        let out = pid.update(&gains(0.5, 1.0, 0.01), 0.2, 0.1);
+        assert!((out - 0.14).abs() < 1e-6, "{out}");
+        // the derivative only sees the change
+        let out = pid.update(&gains(0.0, 0.0, 0.01), 0.2, 0.1);
+        assert_eq!(out, 0.0);
+    }
+
+    #[test]
+    fn integral_freezes_while_saturated() {
+        let mut pid = Pid::default();
+        let integrating = gains(0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            assert!(pid.update(&integrating, 1.0, 0.1) <= LIMIT);
+        }
+        // no windup to unwind, the output follows the sign change at once
+        assert!(pid.update(&integrating, -1.0, 0.1) < LIMIT);
+        pid.reset();
+        assert_eq!(pid.update(&integrating, 0.0, 0.1), 0.0);
+        assert_eq!(pid.update(&integrating, 1.0, 0.0), 0.0);
+    }
+}
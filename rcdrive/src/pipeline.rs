@@ -0,0 +1,164 @@
+//! One tick of the motor task, from the command to the motor frame
+//!
+//! `rcar::motor` and `rcsim` both run it, so the simulator takes the same
+//! path as the car: motion profile, headless rotation, obstacle gate,
+//! kinematics and the calibration of `WheelMan`. Only the speed control on
+//! the wheels is left to the caller.
+
+use rcproto::{CarConfig, Flags, MotionLimits, VelocityCommand};
+
+use crate::{
+    heading::{body_to_world, world_to_body},
+    kinematics, obstacle, Kinematics, MotionProfile, MotorWriteBufs, WheelMan, WheelSpeed,
+};
+
+/// what one tick of a [`Pipeline`] came up with
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tick {
+    /// body velocity `[vx, vy, wz]` after the ramp and the obstacle gate
+    pub body: [f32; 3],
+    /// what the kinematics ask of the wheels
+    pub wheels: WheelSpeed,
+    /// what the wheels are driven at after the speed control
+    pub applied: WheelSpeed,
+    pub bufs: MotorWriteBufs,
+}
+
+pub struct Pipeline {
+    pub profile: MotionProfile,
+    model: &'static dyn Kinematics,
+    wheel_man: WheelMan,
+}
+
+impl Pipeline {
+    pub fn new(config: &CarConfig, limits: MotionLimits) -> Self {
+        Pipeline {
+            profile: MotionProfile::new(limits),
+            model: kinematics::model(config.kinematics),
+            wheel_man: WheelMan::new(config),
+        }
+    }
+
+    pub fn model(&self) -> &'static dyn Kinematics {
+        self.model
+    }
+
+    /// steps `dt` seconds towards `command`, for a car facing `heading` with
+    /// an obstacle ahead while `blocked`
+    ///
+    /// `track` gets the wheel speeds the kinematics ask for and returns the
+    /// ones to drive, the car closes its encoder loops there.
+    pub fn tick(
+        &mut self,
+        command: &VelocityCommand,
+        heading: f32,
+        blocked: bool,
+        dt: f32,
+        track: impl FnOnce(WheelSpeed) -> WheelSpeed,
+    ) -> Tick {
+        let headless = command.flags.contains(Flags::HEADLESS);
+        let [mut x, mut y, z] = self.profile.update(command, dt);
+        if headless {
+            (x, y) = world_to_body(x, y, heading);
+        }
+        // brakes at once instead of ramping down, the obstacle is close
+        let body = obstacle::gate([x, y, z], blocked);
+        if body[1] != y {
+            // and ramps up from the standstill once the way is clear
+            let (mut x, mut y) = (body[0], body[1]);
+            if headless {
+                (x, y) = body_to_world(x, y, heading);
+            }
+            self.profile.hold([x, y, body[2]]);
+        }
+        let wheels = self.model.outputs(body[0], body[1], body[2]);
+        let applied = track(wheels);
+        Tick {
+            body,
+            wheels,
+            applied,
+            bufs: self.wheel_man.bufs(applied),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use rcproto::AxisLimits;
+
+    use super::*;
+
+    /// no ramp, every command is reached within a tick
+    fn instant() -> MotionLimits {
+        let unlimited = AxisLimits {
+            accel: 0.0,
+            decel: 0.0,
+            jerk: 0.0,
+        };
+        MotionLimits {
+            linear: unlimited,
+            angular: unlimited,
+        }
+    }
+
+    #[test]
+    fn headless_turns_the_command_into_the_car_frame() {
+        let mut pipeline = Pipeline::new(&CarConfig::default(), instant());
+        let mut forward = VelocityCommand::new(0.0, 0.5, 0.0);
+        forward.flags = Flags::HEADLESS;
+        let tick = pipeline.tick(&forward, PI / 2.0, false, 0.02, |w| w);
+        assert!((tick.body[0] - 0.5).abs() < 1e-3 && tick.body[1].abs() < 1e-3);
+    }
+
+    #[test]
+    fn obstacle_stops_forward_motion_only() {
+        let mut pipeline = Pipeline::new(&CarConfig::default(), instant());
+        let tick = pipeline.tick(&VelocityCommand::new(0.2, 0.5, 0.1), 0.0, true, 0.02, |w| w);
+        let [x, y, z] = tick.body;
+        assert_eq!(y, 0.0);
+        assert!((x - 0.2).abs() < 1e-6 && (z - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clearing_obstacle_ramps_up_again() {
+        let limits = MotionLimits {
+            linear: AxisLimits {
+                accel: 1.0,
+                decel: 2.0,
+                jerk: 0.0,
+            },
+            ..instant()
+        };
+        let mut pipeline = Pipeline::new(&CarConfig::default(), limits);
+        let forward = VelocityCommand::new(0.0, 0.5, 0.0);
+        for _ in 0..50 {
+            let tick = pipeline.tick(&forward, 0.0, true, 0.02, |w| w);
+            assert_eq!(tick.body[1], 0.0);
+        }
+        // one step of the accel limit, not the half second of ramp above
+        let tick = pipeline.tick(&forward, 0.0, false, 0.02, |w| w);
+        assert!((tick.body[1] - 0.02).abs() < 1e-6, "{}", tick.body[1]);
+    }
+
+    #[test]
+    fn frame_carries_the_tracked_speeds() {
+        let config = CarConfig::default();
+        let mut pipeline = Pipeline::new(&config, instant());
+        let stopped = |_| WheelSpeed::default();
+        let tick = pipeline.tick(
+            &VelocityCommand::new(0.0, 0.5, 0.0),
+            0.0,
+            false,
+            0.02,
+            stopped,
+        );
+        assert_eq!(tick.wheels, WheelSpeed::drive_y(0.5));
+        assert_eq!(
+            tick.bufs,
+            WheelMan::new(&config).bufs(WheelSpeed::default())
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Acceleration and jerk limited ramp from the commanded to the applied velocity
+
+use micromath::F32Ext;
+use rcproto::{AxisLimits, MotionLimits, VelocityCommand};
+
+/// ramps a single axis, run at a fixed rate
+#[derive(Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct AxisProfile {
+    velocity: f32,
+    accel: f32,
+}
+
+impl AxisProfile {
+    fn update(&mut self, target: f32, limits: &AxisLimits, dt: f32) -> f32 {
+        let error = target - self.velocity;
+        if error == 0.0 {
+            self.accel = 0.0;
+            return self.velocity;
+        }
+
+        let speeding_up = self.velocity * target >= 0.0 && target.abs() > self.velocity.abs();
+        let max_accel = if speeding_up {
+            limits.accel
+        } else {
+            limits.decel
+        };
+
+        // the acceleration that would reach the target within this step
+        let mut accel = error / dt;
+        if max_accel > 0.0 {
+            accel = accel.clamp(-max_accel, max_accel);
+        }
+        if limits.jerk > 0.0 {
+            // leave room to ramp the acceleration back down before the target
+            let approach = F32Ext::sqrt(2.0 * limits.jerk * error.abs());
+            accel = accel.clamp(-approach, approach);
+            let max_step = limits.jerk * dt;
+            accel = accel.clamp(self.accel - max_step, self.accel + max_step);
+        }
+
+        self.accel = accel;
+        let velocity = self.velocity + accel * dt;
+        if (target - velocity) * error <= 0.0 {
+            // would overshoot
+            self.velocity = target;
+            self.accel = 0.0;
+        } else {
+            self.velocity = velocity;
+        }
+        self.velocity
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MotionProfile {
+    pub limits: MotionLimits,
+    x: AxisProfile,
+    y: AxisProfile,
+    z: AxisProfile,
+}
+
+impl MotionProfile {
+    pub fn new(limits: MotionLimits) -> Self {
+        MotionProfile {
+            limits,
+            x: AxisProfile::default(),
+            y: AxisProfile::default(),
+            z: AxisProfile::default(),
+        }
+    }
+
+    /// the velocity to apply after stepping `dt` seconds towards `target`
+    pub fn update(&mut self, target: &VelocityCommand, dt: f32) -> [f32; 3] {
+        if dt <= 0.0 {
+            return self.current();
+        }
+        let linear = &self.limits.linear;
+        [
+            self.x.update(target.x, linear, dt),
+            self.y.update(target.y, linear, dt),
+            self.z.update(target.z, &self.limits.angular, dt),
+        ]
+    }
+
+    pub fn current(&self) -> [f32; 3] {
+        [self.x.velocity, self.y.velocity, self.z.velocity]
+    }
+
+    /// goes on from `velocity` instead of where the ramp got to, e.g. after
+    /// something else braked harder
+    pub fn hold(&mut self, velocity: [f32; 3]) {
+        for (axis, v) in [&mut self.x, &mut self.y, &mut self.z]
+            .into_iter()
+            .zip(velocity)
+        {
+            if axis.velocity != v {
+                *axis = AxisProfile {
+                    velocity: v,
+                    accel: 0.0,
+                };
+            }
+        }
+    }
+
+    /// stands still at once, without the braking ramp
+    pub fn stop(&mut self) {
+        self.x = AxisProfile::default();
+        self.y = AxisProfile::default();
+        self.z = AxisProfile::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    fn limits(accel: f32, decel: f32, jerk: f32) -> MotionLimits {
+        let axis = AxisLimits { accel, decel, jerk };
+        MotionLimits {
+            linear: axis,
+            angular: axis,
+        }
+    }
+
+    fn forward(y: f32) -> VelocityCommand {
+        VelocityCommand::new(0.0, y, 0.0)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn ramps_up_at_the_accel_limit() {
+        let mut profile = MotionProfile::new(limits(1.0, 0.0, 0.0));
+        for step in 1..=10 {
+            let [x, y, z] = profile.update(&forward(0.5), DT);
+            assert!(close(y, step as f32 * 0.01), "step {step}: {y}");
+            assert_eq!((x, z), (0.0, 0.0));
+        }
+        for _ in 0..100 {
+            profile.update(&forward(0.5), DT);
+        }
+        assert_eq!(profile.current()[1], 0.5);
+    }
+
+    #[test]
+    fn jerk_limits_the_change_of_acceleration() {
+        let (accel, jerk) = (2.0, 10.0);
+        let mut profile = MotionProfile::new(limits(accel, accel, jerk));
+        let mut last = (0.0, 0.0);
+        for _ in 0..200 {
+            let y = profile.update(&forward(0.5), DT)[1];
+            let a = (y - last.0) / DT;
+            assert!(a <= accel + 1e-3, "accel {a}");
+            // landing on the target ends the ramp within one step
+            if y < 0.5 {
+                assert!(
+                    (a - last.1).abs() <= jerk * DT + 1e-3,
+                    "jerk {}",
+                    a - last.1
+                );
+            }
+            assert!(y <= 0.5, "overshot to {y}");
+            last = (y, a);
+        }
+        assert_eq!(last.0, 0.5);
+    }
+
+    #[test]
+    fn brakes_at_the_decel_limit() {
+        let mut profile = MotionProfile::new(limits(1.0, 4.0, 0.0));
+        while profile.current()[1] < 0.5 {
+            profile.update(&forward(0.5), DT);
+        }
+        let y = profile.update(&forward(0.0), DT)[1];
+        assert!(close(y, 0.46), "{y}");
+        // reversing brakes down to a standstill first
+        let y = profile.update(&forward(-0.5), DT)[1];
+        assert!(close(y, 0.42), "{y}");
+    }
+
+    #[test]
+    fn stop_skips_the_ramp() {
+        let mut profile = MotionProfile::new(limits(1.0, 1.0, 0.0));
+        for _ in 0..20 {
+            profile.update(&VelocityCommand::new(0.5, 0.5, 0.5), DT);
+        }
+        profile.stop();
+        assert_eq!(profile.current(), [0.0; 3]);
+        let y = profile.update(&forward(0.5), DT)[1];
+        assert!(close(y, 0.01), "{y}");
+    }
+
+    #[test]
+    fn limits_change_at_runtime() {
+        let mut profile = MotionProfile::new(limits(1.0, 1.0, 0.0));
+        let y = profile.update(&forward(0.5), DT)[1];
+        assert!(close(y, 0.01), "{y}");
+        profile.limits = limits(3.0, 3.0, 0.0);
+        let y = profile.update(&forward(0.5), DT)[1];
+        assert!(close(y, 0.04), "{y}");
+        // unlimited reaches the target at once
+        profile.limits = limits(0.0, 0.0, 0.0);
+        assert_eq!(profile.update(&forward(-0.5), DT)[1], -0.5);
+    }
+}
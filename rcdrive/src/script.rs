@@ -0,0 +1,200 @@
+//! Runs an uploaded motion script in place of the joystick
+
+use heapless::Vec;
+use rcproto::{ScriptCommand, ScriptState, ScriptStatus, Segment, VelocityCommand, SCRIPT_MAX};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScriptError {
+    /// segments can't change while the script runs
+    Busy,
+    /// more than `SCRIPT_MAX` segments
+    Full,
+    /// nothing to start
+    Empty,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScriptRunner {
+    segments: Vec<Segment, SCRIPT_MAX>,
+    state: ScriptState,
+    index: usize,
+    /// microseconds into the current segment
+    elapsed_us: u32,
+}
+
+impl Default for ScriptRunner {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl ScriptRunner {
+    /// an empty script, usable in statics
+    pub const DEFAULT: ScriptRunner = ScriptRunner {
+        segments: Vec::new(),
+        state: ScriptState::Idle,
+        index: 0,
+        elapsed_us: 0,
+    };
+
+    pub fn status(&self) -> ScriptStatus {
+        ScriptStatus {
+            state: self.state,
+            len: self.segments.len() as u8,
+            index: self.index as u8,
+        }
+    }
+
+    /// true while the script and not the joystick drives the car
+    pub fn active(&self) -> bool {
+        matches!(self.state, ScriptState::Running | ScriptState::Paused)
+    }
+
+    pub fn apply(&mut self, command: &ScriptCommand) -> Result<(), ScriptError> {
+        match command {
+            ScriptCommand::Clear => *self = ScriptRunner::default(),
+            ScriptCommand::Append(_) => {
+                if self.active() {
+                    return Err(ScriptError::Busy);
+                }
+                let count = command.segments().count();
+                if self.segments.len() + count > SCRIPT_MAX {
+                    return Err(ScriptError::Full);
+                }
+                for segment in command.segments() {
+                    // checked above
+                    let _ = self.segments.push(segment);
+                }
+            }
+            ScriptCommand::Start if self.state == ScriptState::Paused => {
+                self.state = ScriptState::Running;
+            }
+            ScriptCommand::Start => {
+                if self.segments.is_empty() {
+                    return Err(ScriptError::Empty);
+                }
+                self.state = ScriptState::Running;
+                self.index = 0;
+                self.elapsed_us = 0;
+            }
+            ScriptCommand::Pause if self.state == ScriptState::Running => {
+                self.state = ScriptState::Paused;
+            }
+            ScriptCommand::Abort if self.active() => self.abort(),
+            ScriptCommand::Pause | ScriptCommand::Abort => {}
+        }
+        Ok(())
+    }
+
+    pub fn abort(&mut self) {
+        if self.active() {
+            self.state = ScriptState::Aborted;
+        }
+    }
+
+    /// the command for the next `dt_us`, `None` leaves the car to the joystick
+    pub fn tick(&mut self, dt_us: u32) -> Option<VelocityCommand> {
+        match self.state {
+            ScriptState::Paused => return Some(VelocityCommand::default()),
+            ScriptState::Running => {}
+            _ => return None,
+        }
+        let segment = loop {
+            let Some(segment) = self.segments.get(self.index) else {
+                self.state = ScriptState::Done;
+                self.index = 0;
+                return None;
+            };
+            let duration_us = segment.duration_ms as u32 * 1000;
+            if self.elapsed_us < duration_us {
+                break segment;
+            }
+            self.elapsed_us -= duration_us;
+            self.index += 1;
+        };
+        self.elapsed_us = self.elapsed_us.saturating_add(dt_us);
+        Some(VelocityCommand::new(segment.vx, segment.vy, segment.wz))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(runner: &mut ScriptRunner, segments: &[Segment]) -> Result<(), ScriptError> {
+        let mut buf = std::vec![1];
+        for s in segments {
+            buf.extend_from_slice(&s.encode());
+        }
+        runner.apply(&ScriptCommand::decode(&buf).unwrap())
+    }
+
+    fn segment(vy: f32, duration_ms: u16) -> Segment {
+        Segment {
+            vy,
+            duration_ms,
+            ..Segment::default()
+        }
+    }
+
+    #[test]
+    fn runs_segments_in_order() {
+        let mut runner = ScriptRunner::default();
+        append(&mut runner, &[segment(0.5, 100), segment(-0.5, 50)]).unwrap();
+        assert_eq!(runner.tick(20_000), None);
+        runner.apply(&ScriptCommand::Start).unwrap();
+
+        let ys: std::vec::Vec<_> = (0..10).map(|_| runner.tick(20_000).map(|c| c.y)).collect();
+        // the last segment runs to the end of the tick it ends in
+        let (forward, back) = (Some(0.5), Some(-0.5));
+        assert_eq!(
+            ys,
+            [forward, forward, forward, forward, forward, back, back, back, None, None]
+        );
+        assert_eq!(runner.status().state, ScriptState::Done);
+    }
+
+    #[test]
+    fn pause_holds_still_and_resumes() {
+        let mut runner = ScriptRunner::default();
+        append(&mut runner, &[segment(0.5, 100)]).unwrap();
+        runner.apply(&ScriptCommand::Start).unwrap();
+        runner.tick(60_000);
+        runner.apply(&ScriptCommand::Pause).unwrap();
+        assert_eq!(runner.tick(1_000_000), Some(VelocityCommand::default()));
+        assert!(runner.active());
+        runner.apply(&ScriptCommand::Start).unwrap();
+        assert_eq!(runner.tick(20_000).map(|c| c.y), Some(0.5));
+        assert_eq!(runner.tick(20_000).map(|c| c.y), Some(0.5));
+        assert_eq!(runner.tick(20_000), None);
+    }
+
+    #[test]
+    fn abort_hands_back_control() {
+        let mut runner = ScriptRunner::default();
+        append(&mut runner, &[segment(0.5, 1000)]).unwrap();
+        runner.apply(&ScriptCommand::Start).unwrap();
+        assert_eq!(
+            append(&mut runner, &[segment(0.1, 10)]),
+            Err(ScriptError::Busy)
+        );
+        runner.apply(&ScriptCommand::Abort).unwrap();
+        assert_eq!(runner.tick(20_000), None);
+        assert_eq!(runner.status().state, ScriptState::Aborted);
+    }
+
+    #[test]
+    fn bounded_and_clearable() {
+        let mut runner = ScriptRunner::default();
+        assert_eq!(runner.apply(&ScriptCommand::Start), Err(ScriptError::Empty));
+        let eight = [segment(0.1, 10); 8];
+        for _ in 0..SCRIPT_MAX / 8 {
+            append(&mut runner, &eight).unwrap();
+        }
+        assert_eq!(append(&mut runner, &eight[..1]), Err(ScriptError::Full));
+        assert_eq!(runner.status().len as usize, SCRIPT_MAX);
+        runner.apply(&ScriptCommand::Clear).unwrap();
+        assert_eq!(runner.status(), ScriptStatus::default());
+    }
+}
@@ -0,0 +1,117 @@
+//! Normalized speed of every wheel of the mecanum car
+
+use core::ops::{Add, Mul};
+
+use micromath::vector::{F32x2, Vector};
+
+type Vec2 = F32x2;
+
+#[derive(Clone, Default, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WheelSpeed {
+    pub front_left: f32,
+    pub front_right: f32,
+    pub back_left: f32,
+    pub back_right: f32,
+}
+impl WheelSpeed {
+    pub fn drive_y(y: f32) -> WheelSpeed {
+        WheelSpeed {
+            front_left: y,
+            back_left: y,
+            front_right: -y,
+            back_right: -y,
+        }
+    }
+    pub fn drive_x(x: f32) -> WheelSpeed {
+        WheelSpeed {
+            front_left: x,
+            back_left: -x,
+            front_right: x,
+            back_right: -x,
+        }
+    }
+    pub fn drive_z(z: f32) -> WheelSpeed {
+        WheelSpeed {
+            front_left: -z,
+            back_left: -z,
+            front_right: -z,
+            back_right: -z,
+        }
+    }
+
+    pub fn translate(x: f32, y: f32) -> WheelSpeed {
+        (WheelSpeed::drive_x(x) + WheelSpeed::drive_y(y)).clamp1()
+    }
+    pub fn trans_rotate(x: f32, y: f32, z: f32) -> WheelSpeed {
+        let xy_mag = Vec2 { x, y }.magnitude();
+        let z = z / (2.0 - xy_mag);
+        (WheelSpeed::drive_x(x) + WheelSpeed::drive_y(y) + WheelSpeed::drive_z(z)).clamp1()
+    }
+
+    pub fn from_array([front_left, back_left, front_right, back_right]: [f32; 4]) -> WheelSpeed {
+        WheelSpeed {
+            front_left,
+            front_right,
+            back_left,
+            back_right,
+        }
+    }
+    pub fn to_array(&self) -> [f32; 4] {
+        [
+            self.front_left,
+            self.back_left,
+            self.front_right,
+            self.back_right,
+        ]
+    }
+    pub fn absmax(&self) -> f32 {
+        self.to_array()
+            .map(|e| e.abs())
+            .iter()
+            .fold(0.0, |acc, v| acc.max(*v))
+    }
+
+    pub fn clamp1(self) -> WheelSpeed {
+        let max = self.absmax();
+        if max < 1.0 {
+            return self;
+        }
+        self * (1.0 / max)
+    }
+}
+
+impl Add<f32> for WheelSpeed {
+    type Output = WheelSpeed;
+
+    fn add(mut self, rhs: f32) -> Self::Output {
+        self.front_left += rhs;
+        self.back_left += rhs;
+        self.front_right += rhs;
+        self.back_right += rhs;
+        self
+    }
+}
+impl Mul<f32> for WheelSpeed {
+    type Output = WheelSpeed;
+
+    fn mul(mut self, rhs: f32) -> Self::Output {
+        self.front_left *= rhs;
+        self.back_left *= rhs;
+        self.front_right *= rhs;
+        self.back_right *= rhs;
+        self
+    }
+}
+
+impl Add for WheelSpeed {
+    type Output = WheelSpeed;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.front_left += rhs.front_left;
+        self.back_left += rhs.back_left;
+        self.front_right += rhs.front_right;
+        self.back_right += rhs.back_right;
+        self
+    }
+}
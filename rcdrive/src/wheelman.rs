@@ -0,0 +1,151 @@
+//! Turns wheel speeds into the frames written to the motor board
+
+use rcproto::{CarConfig, WheelCalibration};
+
+use crate::WheelSpeed;
+
+/// `[channel, output]` of front left, front right, back left and back right
+pub type MotorWriteBufs = [[u8; 2]; 4];
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WheelMan {
+    pub front_left: u8,
+    pub front_right: u8,
+    pub back_left: u8,
+    pub back_right: u8,
+    /// motor output at full speed, relative to neutral
+    pub scale: f32,
+    /// in the order of `WheelSpeed::to_array`
+    pub calibration: [WheelCalibration; 4],
+}
+
+impl WheelMan {
+    pub fn new(config: &CarConfig) -> Self {
+        let [front_left, back_left, front_right, back_right] = config.motor_channels;
+        WheelMan {
+            front_left,
+            front_right,
+            back_left,
+            back_right,
+            scale: config.speed_scale,
+            calibration: config.calibration,
+        }
+    }
+    pub fn transform_bufs(&self, x: f32, y: f32) -> MotorWriteBufs {
+        self.bufs(WheelSpeed::translate(x, y))
+    }
+    pub fn trans_rotate_bufs(&self, x: f32, y: f32, z: f32) -> MotorWriteBufs {
+        self.bufs(WheelSpeed::trans_rotate(x, y, z))
+    }
+    pub fn bufs(&self, speeds: WheelSpeed) -> MotorWriteBufs {
+        let [front_left, back_left, front_right, back_right] = speeds.to_array();
+        let [cal_fl, cal_bl, cal_fr, cal_br] = &self.calibration;
+        [
+            [self.front_left, speed_byte(cal_fl, front_left, self.scale)],
+            [
+                self.front_right,
+                speed_byte(cal_fr, front_right, self.scale),
+            ],
+            [self.back_left, speed_byte(cal_bl, back_left, self.scale)],
+            [self.back_right, speed_byte(cal_br, back_right, self.scale)],
+        ]
+    }
+}
+
+/// motor output of a single wheel, `scale` is the output at full speed
+pub fn speed_byte(cal: &WheelCalibration, speed: f32, scale: f32) -> u8 {
+    let speed = if cal.invert { -speed } else { speed };
+    if speed.abs() <= cal.deadband {
+        return cal.neutral;
+    }
+    let gain = if speed > 0.0 {
+        cal.forward_gain
+    } else {
+        cal.reverse_gain
+    };
+    (speed * gain * scale + cal.neutral as f32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cal() -> WheelCalibration {
+        WheelCalibration::default()
+    }
+
+    #[test]
+    fn default_matches_uncalibrated_mapping() {
+        for speed in [-1.0, -0.5, -0.01, 0.0, 0.3, 1.0] {
+            let expected = (speed * 90.0 + 90.0) as u8;
+            assert_eq!(speed_byte(&cal(), speed, 90.0), expected, "speed {speed}");
+        }
+    }
+
+    #[test]
+    fn neutral_offset() {
+        let c = WheelCalibration {
+            neutral: 94,
+            ..cal()
+        };
+        assert_eq!(speed_byte(&c, 0.0, 90.0), 94);
+        assert_eq!(speed_byte(&c, 0.5, 80.0), 134);
+        assert_eq!(speed_byte(&c, -0.5, 80.0), 54);
+    }
+
+    #[test]
+    fn deadband_holds_neutral() {
+        let c = WheelCalibration {
+            deadband: 0.1,
+            ..cal()
+        };
+        assert_eq!(speed_byte(&c, 0.1, 90.0), 90);
+        assert_eq!(speed_byte(&c, -0.05, 90.0), 90);
+        assert_eq!(speed_byte(&c, 0.2, 90.0), 108);
+    }
+
+    #[test]
+    fn gains_per_direction() {
+        let c = WheelCalibration {
+            forward_gain: 0.5,
+            reverse_gain: 0.8,
+            ..cal()
+        };
+        assert_eq!(speed_byte(&c, 1.0, 90.0), 135);
+        assert_eq!(speed_byte(&c, -1.0, 90.0), 18);
+    }
+
+    #[test]
+    fn invert_uses_gain_of_the_actual_direction() {
+        let c = WheelCalibration {
+            invert: true,
+            forward_gain: 0.5,
+            ..cal()
+        };
+        assert_eq!(speed_byte(&c, 1.0, 90.0), 0);
+        assert_eq!(speed_byte(&c, -1.0, 90.0), 135);
+    }
+
+    #[test]
+    fn output_saturates() {
+        let c = WheelCalibration {
+            forward_gain: 2.0,
+            reverse_gain: 2.0,
+            ..cal()
+        };
+        assert_eq!(speed_byte(&c, 1.0, 90.0), 255);
+        assert_eq!(speed_byte(&c, -1.0, 90.0), 0);
+    }
+
+    #[test]
+    fn bufs_apply_per_wheel_calibration() {
+        let mut config = CarConfig::default();
+        config.calibration[1].neutral = 100;
+        config.calibration[2].invert = true;
+        let man = WheelMan::new(&config);
+        let bufs = man.bufs(WheelSpeed::from_array([0.0, 0.0, 0.5, 0.0]));
+        // front left, front right, back left, back right
+        assert_eq!(bufs, [[4, 90], [6, 45], [5, 100], [7, 90]]);
+    }
+}
@@ -0,0 +1,558 @@
+//! Per car setup, stored on the car and edited over BLE
+
+use crate::{read_f32, read_f32s, write_f32s, DecodeError};
+
+/// encoded size of [`WheelCalibration`]
+pub const CALIBRATION_LEN: usize = 2 + 3 * 4;
+
+/// corrections for a motor that isn't centered or symmetric
+///
+/// layout: `[neutral, invert, deadband: f32, forward_gain: f32, reverse_gain: f32]`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WheelCalibration {
+    /// motor output at which the wheel stands still
+    pub neutral: u8,
+    /// the wheel turns the other way around
+    pub invert: bool,
+    /// speeds closer to zero than this leave the wheel at neutral
+    pub deadband: f32,
+    /// output gain when driving forward
+    pub forward_gain: f32,
+    /// output gain when driving in reverse
+    pub reverse_gain: f32,
+}
+
+impl Default for WheelCalibration {
+    fn default() -> Self {
+        WheelCalibration {
+            neutral: 90,
+            invert: false,
+            deadband: 0.0,
+            forward_gain: 1.0,
+            reverse_gain: 1.0,
+        }
+    }
+}
+
+impl WheelCalibration {
+    pub fn encode(&self) -> [u8; CALIBRATION_LEN] {
+        let mut buf = [0; CALIBRATION_LEN];
+        buf[0] = self.neutral;
+        buf[1] = self.invert as u8;
+        crate::write_f32s(
+            &mut buf[2..],
+            &[self.deadband, self.forward_gain, self.reverse_gain],
+        );
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != CALIBRATION_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let [deadband, forward_gain, reverse_gain] = crate::read_f32s(&buf[2..])?;
+        if buf[1] > 1 || !(0.0..1.0).contains(&deadband) {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(WheelCalibration {
+            neutral: buf[0],
+            invert: buf[1] == 1,
+            deadband,
+            forward_gain,
+            reverse_gain,
+        })
+    }
+}
+
+/// encoded size of [`BatteryConfig`]
+pub const BATTERY_CONFIG_LEN: usize = 4 + 3 * 2;
+
+/// how the pack voltage is measured and when it runs low, see `rcdrive::battery`
+///
+/// layout: `[divider: f32, full_mv: u16, low_mv: u16, cutoff_mv: u16]`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryConfig {
+    /// pack voltage per volt at the analog pin, 0 when there is no divider
+    pub divider: f32,
+    /// pack voltage reported as full
+    pub full_mv: u16,
+    /// below this the speed is limited
+    pub low_mv: u16,
+    /// below this the motors are cut, reported as empty
+    pub cutoff_mv: u16,
+}
+
+impl Default for BatteryConfig {
+    /// a 2S LiPo, cut at 3.3V per cell, not monitored until a divider is
+    /// set
+    fn default() -> Self {
+        BatteryConfig {
+            divider: 0.0,
+            full_mv: 8400,
+            low_mv: 7000,
+            cutoff_mv: 6600,
+        }
+    }
+}
+
+impl BatteryConfig {
+    pub fn encode(&self) -> [u8; BATTERY_CONFIG_LEN] {
+        let mut buf = [0; BATTERY_CONFIG_LEN];
+        buf[0..4].copy_from_slice(&self.divider.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.full_mv.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.low_mv.to_le_bytes());
+        buf[8..10].copy_from_slice(&self.cutoff_mv.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != BATTERY_CONFIG_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let divider = read_f32(&buf[0..4]);
+        if !divider.is_finite() {
+            return Err(DecodeError::NotFinite);
+        }
+        let mv = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let config = BatteryConfig {
+            divider,
+            full_mv: mv(4),
+            low_mv: mv(6),
+            cutoff_mv: mv(8),
+        };
+        if divider < 0.0 || config.cutoff_mv >= config.low_mv || config.low_mv >= config.full_mv {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(config)
+    }
+}
+
+/// how body velocity maps onto the motors, see `rcdrive::kinematics`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KinematicsModel {
+    #[default]
+    Mecanum = 0,
+    /// two driven wheels
+    Differential = 1,
+    /// four wheels, driven per side
+    SkidSteer = 2,
+    /// a drive motor and a steering servo
+    Ackermann = 3,
+}
+
+impl TryFrom<u8> for KinematicsModel {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(KinematicsModel::Mecanum),
+            1 => Ok(KinematicsModel::Differential),
+            2 => Ok(KinematicsModel::SkidSteer),
+            3 => Ok(KinematicsModel::Ackermann),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// what counts the wheel turns, see `rcar::encoder`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncoderKind {
+    #[default]
+    None = 0,
+    /// two channels, counts carry the direction
+    Quadrature = 1,
+    /// a single pulse channel, counts only ever go up
+    Hall = 2,
+}
+
+impl TryFrom<u8> for EncoderKind {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(EncoderKind::None),
+            1 => Ok(EncoderKind::Quadrature),
+            2 => Ok(EncoderKind::Hall),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// the board or chip the motors are wired to, see `rcdrive::driver`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotorDriverKind {
+    /// Elecfreaks Wukong board over i2c
+    #[default]
+    Wukong = 0,
+    /// PCA9685 servo/pwm board over i2c
+    Pca9685 = 1,
+    /// DRV8833 or TB6612 h-bridges on the pwm peripheral
+    HBridge = 2,
+}
+
+impl MotorDriverKind {
+    /// motor channels run from 0 to below this
+    pub fn channels(self) -> u8 {
+        match self {
+            // the board ignores what it doesn't have
+            MotorDriverKind::Wukong => u8::MAX,
+            MotorDriverKind::Pca9685 => 16,
+            // two motors on each of the two pwm peripherals
+            MotorDriverKind::HBridge => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for MotorDriverKind {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(MotorDriverKind::Wukong),
+            1 => Ok(MotorDriverKind::Pca9685),
+            2 => Ok(MotorDriverKind::HBridge),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// bumped whenever the layout of [`CarConfig`] changes
+pub const CONFIG_VERSION: u8 = 9;
+/// longest name that still fits the advertisement
+pub const NAME_MAX: usize = 20;
+/// written as the instructor pin, turns the instructor off, see
+/// [`CarConfig::written_pin`]
+pub const PIN_CLEAR: u32 = u32::MAX;
+/// largest `CarConfig::speed_scale`, a motor output is a single byte
+pub const MAX_SPEED_SCALE: f32 = 255.0;
+/// largest 7 bit i2c address
+pub const MAX_I2C_ADDRESS: u8 = 0x7f;
+/// encoded size of [`CarConfig`]
+pub const CONFIG_LEN: usize =
+    1 + 1 + 4 + 4 + 1 + NAME_MAX + 4 * CALIBRATION_LEN + 1 + 1 + BATTERY_CONFIG_LEN + 4 + 9 + 1 + 1;
+
+const NAME_END: usize = 11 + NAME_MAX;
+const CALIBRATION_END: usize = NAME_END + 4 * CALIBRATION_LEN;
+const BATTERY_START: usize = CALIBRATION_END + 2;
+const BATTERY_END: usize = BATTERY_START + BATTERY_CONFIG_LEN;
+const PIN_END: usize = BATTERY_END + 4;
+const HARD_IRON_END: usize = PIN_END + 9;
+
+/// per car setup, kept in flash on the car
+///
+/// layout: `[version, i2c_address, motor_channels: [u8; 4], speed_scale: f32,
+/// name_len, name: [u8; NAME_MAX], calibration: [WheelCalibration; 4],
+/// kinematics, driver, battery: BatteryConfig, instructor_pin: u32,
+/// calibrated, hard_iron: [f32; 2], sonar, encoders]`, numbers little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CarConfig {
+    /// i2c address of the motor board, at most [`MAX_I2C_ADDRESS`], unused by
+    /// the h-bridges
+    pub i2c_address: u8,
+    /// motor driver channel of front left, back left, front right and back right
+    pub motor_channels: [u8; 4],
+    /// motor output at full speed, relative to neutral, above 0 and at most
+    /// [`MAX_SPEED_SCALE`]
+    pub speed_scale: f32,
+    name_len: u8,
+    name: [u8; NAME_MAX],
+    /// in the same wheel order as `motor_channels`
+    pub calibration: [WheelCalibration; 4],
+    pub kinematics: KinematicsModel,
+    pub driver: MotorDriverKind,
+    pub battery: BatteryConfig,
+    /// lets a client override the driver, 0 for no instructor
+    ///
+    /// The car reads it back as 0, see [`CarConfig::written_pin`].
+    pub instructor_pin: u32,
+    /// magnetometer offset from the last compass calibration, headless
+    /// driving needs one
+    pub hard_iron: Option<[f32; 2]>,
+    /// an ultrasonic sensor is fitted, see `rcar::sonar`
+    pub sonar: bool,
+    /// the same kind on every wheel
+    pub encoders: EncoderKind,
+}
+
+impl Default for CarConfig {
+    fn default() -> Self {
+        let mut config = CarConfig {
+            i2c_address: 0x10,
+            motor_channels: [4, 5, 6, 7],
+            speed_scale: 90.0,
+            name_len: 0,
+            name: [0; NAME_MAX],
+            calibration: [WheelCalibration::default(); 4],
+            kinematics: KinematicsModel::Mecanum,
+            driver: MotorDriverKind::Wukong,
+            battery: BatteryConfig::default(),
+            instructor_pin: 0,
+            hard_iron: None,
+            sonar: false,
+            encoders: EncoderKind::None,
+        };
+        config.set_name("rcar").unwrap();
+        config
+    }
+}
+
+impl CarConfig {
+    /// name used for the gap device name and in advertisements
+    pub fn name(&self) -> &str {
+        // only ever set from valid utf8
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("rcar")
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<(), DecodeError> {
+        if name.is_empty() || name.len() > NAME_MAX {
+            return Err(DecodeError::Invalid);
+        }
+        self.name = [0; NAME_MAX];
+        self.name[..name.len()].copy_from_slice(name.as_bytes());
+        self.name_len = name.len() as u8;
+        Ok(())
+    }
+
+    /// the pin to keep when this config is written over one with `stored`
+    ///
+    /// Reads show 0 for the pin, so writing 0 back keeps `stored`, while
+    /// [`PIN_CLEAR`] turns the instructor off.
+    pub fn written_pin(&self, stored: u32) -> u32 {
+        match self.instructor_pin {
+            0 => stored,
+            PIN_CLEAR => 0,
+            pin => pin,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; CONFIG_LEN] {
+        let mut buf = [0; CONFIG_LEN];
+        buf[0] = CONFIG_VERSION;
+        buf[1] = self.i2c_address;
+        buf[2..6].copy_from_slice(&self.motor_channels);
+        buf[6..10].copy_from_slice(&self.speed_scale.to_le_bytes());
+        buf[10] = self.name_len;
+        buf[11..NAME_END].copy_from_slice(&self.name);
+        let calibrations = buf[NAME_END..CALIBRATION_END].chunks_exact_mut(CALIBRATION_LEN);
+        for (chunk, cal) in calibrations.zip(&self.calibration) {
+            chunk.copy_from_slice(&cal.encode());
+        }
+        buf[CALIBRATION_END] = self.kinematics as u8;
+        buf[CALIBRATION_END + 1] = self.driver as u8;
+        buf[BATTERY_START..BATTERY_END].copy_from_slice(&self.battery.encode());
+        buf[BATTERY_END..PIN_END].copy_from_slice(&self.instructor_pin.to_le_bytes());
+        if let Some(offset) = self.hard_iron {
+            buf[PIN_END] = 1;
+            write_f32s(&mut buf[PIN_END + 1..HARD_IRON_END], &offset);
+        }
+        buf[HARD_IRON_END] = self.sonar as u8;
+        buf[HARD_IRON_END + 1] = self.encoders as u8;
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != CONFIG_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        if buf[0] != CONFIG_VERSION {
+            return Err(DecodeError::Version(buf[0]));
+        }
+        let speed_scale = read_f32(&buf[6..10]);
+        if !speed_scale.is_finite() {
+            return Err(DecodeError::NotFinite);
+        }
+        if speed_scale <= 0.0 || speed_scale > MAX_SPEED_SCALE || buf[1] > MAX_I2C_ADDRESS {
+            return Err(DecodeError::Invalid);
+        }
+        let mut config = CarConfig {
+            i2c_address: buf[1],
+            motor_channels: [buf[2], buf[3], buf[4], buf[5]],
+            speed_scale,
+            ..CarConfig::default()
+        };
+        let name_len = (buf[10] as usize).min(NAME_MAX);
+        let name =
+            core::str::from_utf8(&buf[11..11 + name_len]).map_err(|_| DecodeError::Invalid)?;
+        config.set_name(name)?;
+        let calibrations = buf[NAME_END..CALIBRATION_END].chunks_exact(CALIBRATION_LEN);
+        for (cal, chunk) in config.calibration.iter_mut().zip(calibrations) {
+            *cal = WheelCalibration::decode(chunk)?;
+        }
+        config.kinematics = buf[CALIBRATION_END].try_into()?;
+        config.driver = buf[CALIBRATION_END + 1].try_into()?;
+        if config
+            .motor_channels
+            .iter()
+            .any(|c| *c >= config.driver.channels())
+        {
+            return Err(DecodeError::Invalid);
+        }
+        config.battery = BatteryConfig::decode(&buf[BATTERY_START..BATTERY_END])?;
+        config.instructor_pin = u32::from_le_bytes([
+            buf[BATTERY_END],
+            buf[BATTERY_END + 1],
+            buf[BATTERY_END + 2],
+            buf[BATTERY_END + 3],
+        ]);
+        config.hard_iron = match buf[PIN_END] {
+            0 => None,
+            1 => Some(read_f32s(&buf[PIN_END + 1..HARD_IRON_END])?),
+            _ => return Err(DecodeError::Invalid),
+        };
+        config.sonar = match buf[HARD_IRON_END] {
+            0 => false,
+            1 => true,
+            _ => return Err(DecodeError::Invalid),
+        };
+        config.encoders = buf[HARD_IRON_END + 1].try_into()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_roundtrip() {
+        let mut config = CarConfig {
+            i2c_address: 0x40,
+            motor_channels: [0, 1, 2, 3],
+            ..CarConfig::default()
+        };
+        config.calibration[2] = WheelCalibration {
+            neutral: 93,
+            invert: true,
+            deadband: 0.05,
+            forward_gain: 0.9,
+            reverse_gain: 1.1,
+        };
+        config.kinematics = KinematicsModel::Ackermann;
+        config.driver = MotorDriverKind::Pca9685;
+        config.battery.cutoff_mv = 3300;
+        config.instructor_pin = 4711;
+        config.hard_iron = Some([-120.5, 48.0]);
+        config.sonar = true;
+        config.encoders = EncoderKind::Hall;
+        config.set_name("rcar-blue").unwrap();
+        let buf = config.encode();
+        assert_eq!(buf[0], CONFIG_VERSION);
+        let decoded = CarConfig::decode(&buf).unwrap();
+        assert_eq!(decoded, config);
+        assert_eq!(decoded.name(), "rcar-blue");
+    }
+
+    #[test]
+    fn config_rejects_bad_input() {
+        let erased = [0xff; CONFIG_LEN];
+        assert_eq!(CarConfig::decode(&erased), Err(DecodeError::Version(0xff)));
+
+        for speed_scale in [-90.0, 0.0, MAX_SPEED_SCALE + 1.0] {
+            let bad_scale = CarConfig {
+                speed_scale,
+                ..CarConfig::default()
+            };
+            assert_eq!(
+                CarConfig::decode(&bad_scale.encode()),
+                Err(DecodeError::Invalid)
+            );
+        }
+
+        let mut wide_address = CarConfig::default().encode();
+        wide_address[1] = MAX_I2C_ADDRESS + 1;
+        assert_eq!(CarConfig::decode(&wide_address), Err(DecodeError::Invalid));
+
+        let mut unknown_model = CarConfig::default().encode();
+        unknown_model[CALIBRATION_END] = 9;
+        assert_eq!(CarConfig::decode(&unknown_model), Err(DecodeError::Invalid));
+
+        let mut unknown_driver = CarConfig::default().encode();
+        unknown_driver[CALIBRATION_END + 1] = 3;
+        assert_eq!(
+            CarConfig::decode(&unknown_driver),
+            Err(DecodeError::Invalid)
+        );
+
+        let hbridge = CarConfig {
+            driver: MotorDriverKind::HBridge,
+            ..CarConfig::default()
+        };
+        assert_eq!(
+            CarConfig::decode(&hbridge.encode()),
+            Err(DecodeError::Invalid)
+        );
+        let hbridge = CarConfig {
+            motor_channels: [0, 1, 2, 3],
+            ..hbridge
+        };
+        assert_eq!(CarConfig::decode(&hbridge.encode()), Ok(hbridge));
+
+        let mut uncalibrated = CarConfig::default().encode();
+        uncalibrated[PIN_END] = 2;
+        assert_eq!(CarConfig::decode(&uncalibrated), Err(DecodeError::Invalid));
+
+        let mut sonar = CarConfig::default().encode();
+        sonar[HARD_IRON_END] = 2;
+        assert_eq!(CarConfig::decode(&sonar), Err(DecodeError::Invalid));
+
+        let mut encoders = CarConfig::default().encode();
+        encoders[HARD_IRON_END + 1] = 3;
+        assert_eq!(CarConfig::decode(&encoders), Err(DecodeError::Invalid));
+
+        let mut nameless = CarConfig::default().encode();
+        nameless[10] = 0;
+        assert_eq!(CarConfig::decode(&nameless), Err(DecodeError::Invalid));
+
+        let mut config = CarConfig::default();
+        assert!(config.set_name("a name that is too long").is_err());
+        assert_eq!(config.name(), "rcar");
+    }
+
+    #[test]
+    fn zero_keeps_the_pin() {
+        let mut written = CarConfig::default();
+        assert_eq!(written.written_pin(4711), 4711);
+        written.instructor_pin = 1234;
+        assert_eq!(written.written_pin(4711), 1234);
+        written.instructor_pin = PIN_CLEAR;
+        assert_eq!(written.written_pin(4711), 0);
+    }
+
+    #[test]
+    fn battery_thresholds_must_be_ordered() {
+        let swapped = BatteryConfig {
+            low_mv: 6000,
+            ..BatteryConfig::default()
+        };
+        assert_eq!(
+            BatteryConfig::decode(&swapped.encode()),
+            Err(DecodeError::Invalid)
+        );
+
+        let off = BatteryConfig::default();
+        assert_eq!(off.divider, 0.0);
+        assert_eq!(BatteryConfig::decode(&off.encode()), Ok(off));
+    }
+
+    #[test]
+    fn calibration_rejects_bad_input() {
+        let mut buf = WheelCalibration::default().encode();
+        buf[1] = 2;
+        assert_eq!(WheelCalibration::decode(&buf), Err(DecodeError::Invalid));
+
+        let wide = WheelCalibration {
+            deadband: 1.0,
+            ..WheelCalibration::default()
+        };
+        assert_eq!(
+            WheelCalibration::decode(&wide.encode()),
+            Err(DecodeError::Invalid)
+        );
+    }
+}
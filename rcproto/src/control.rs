@@ -0,0 +1,144 @@
+//! Who drives the car when several clients are connected
+//!
+//! Every connection sits in a numbered slot. The driver is the only client
+//! whose drive writes the car takes, the others watch.
+
+use crate::DecodeError;
+
+/// encoded size of [`ControlRequest`]
+pub const CONTROL_REQUEST_LEN: usize = 1 + 1 + 4;
+/// encoded size of [`ControlStatus`]
+pub const CONTROL_STATUS_LEN: usize = 3;
+/// no client, on the wire
+pub const NO_SLOT: u8 = u8::MAX;
+
+/// a write to the control characteristic
+///
+/// layout: `[op, slot, pin: u32]`, little endian, unused fields are zero
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlRequest {
+    /// become the driver, if nobody drives
+    Claim,
+    /// stop being the driver
+    Release,
+    /// the driver passes control to the client in `slot`
+    Handover { slot: u8 },
+    /// the instructor takes control from whoever drives, with the
+    /// `instructor_pin` of the car config
+    Override { pin: u32 },
+}
+
+impl ControlRequest {
+    pub fn encode(&self) -> [u8; CONTROL_REQUEST_LEN] {
+        let mut buf = [0; CONTROL_REQUEST_LEN];
+        match *self {
+            ControlRequest::Claim => buf[0] = 0,
+            ControlRequest::Release => buf[0] = 1,
+            ControlRequest::Handover { slot } => {
+                buf[0] = 2;
+                buf[1] = slot;
+            }
+            ControlRequest::Override { pin } => {
+                buf[0] = 3;
+                buf[2..].copy_from_slice(&pin.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != CONTROL_REQUEST_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        match buf[0] {
+            0 => Ok(ControlRequest::Claim),
+            1 => Ok(ControlRequest::Release),
+            2 => Ok(ControlRequest::Handover { slot: buf[1] }),
+            3 => Ok(ControlRequest::Override {
+                pin: u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]),
+            }),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// the connection table as one client sees it
+///
+/// layout: `[you, driver, connected]`, slots as numbers or [`NO_SLOT`],
+/// `connected` with a bit per slot. Reads leave out `you`, only
+/// notifications know who they go to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ControlStatus {
+    pub you: Option<u8>,
+    pub driver: Option<u8>,
+    pub connected: u8,
+}
+
+impl ControlStatus {
+    /// whether the client this went to drives
+    pub fn driving(&self) -> bool {
+        self.you.is_some() && self.you == self.driver
+    }
+
+    pub fn encode(&self) -> [u8; CONTROL_STATUS_LEN] {
+        [
+            self.you.unwrap_or(NO_SLOT),
+            self.driver.unwrap_or(NO_SLOT),
+            self.connected,
+        ]
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != CONTROL_STATUS_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let slot = |b: u8| Some(b).filter(|b| *b != NO_SLOT);
+        Ok(ControlStatus {
+            you: slot(buf[0]),
+            driver: slot(buf[1]),
+            connected: buf[2],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_roundtrip() {
+        let requests = [
+            ControlRequest::Claim,
+            ControlRequest::Release,
+            ControlRequest::Handover { slot: 2 },
+            ControlRequest::Override { pin: 271828 },
+        ];
+        for request in requests {
+            assert_eq!(ControlRequest::decode(&request.encode()), Ok(request));
+        }
+        assert_eq!(ControlRequest::Claim.encode(), [0; CONTROL_REQUEST_LEN]);
+        assert_eq!(
+            ControlRequest::decode(&[4, 0, 0, 0, 0, 0]),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(ControlRequest::decode(&[0]), Err(DecodeError::Length(1)));
+    }
+
+    #[test]
+    fn status_roundtrip() {
+        let status = ControlStatus {
+            you: Some(1),
+            driver: Some(1),
+            connected: 0b011,
+        };
+        assert!(status.driving());
+        assert_eq!(status.encode(), [1, 1, 0b011]);
+        assert_eq!(ControlStatus::decode(&status.encode()), Ok(status));
+
+        let read = ControlStatus::decode(&[NO_SLOT, NO_SLOT, 0]).unwrap();
+        assert_eq!(read, ControlStatus::default());
+        assert!(!read.driving());
+    }
+}
@@ -0,0 +1,105 @@
+//! Per device BLE identity, derived from the 64 bit device id in the FICR
+//!
+//! Every car gets its own random static address, and a name suffix with the
+//! last digits of that address, so cars in one room can be told apart.
+
+use crate::{NAME_MAX, SERVICE_UUID};
+
+/// `-` and four hex digits
+pub const NAME_SUFFIX_LEN: usize = 5;
+/// longest name with its suffix, still fits the advertisement
+pub const UNIQUE_NAME_MAX: usize = NAME_MAX + NAME_SUFFIX_LEN;
+
+/// the 46 bits of a static address that are not the type
+const RANDOM_MASK: u64 = 0x3fff_ffff_ffff;
+
+/// random static address of the device, least significant byte first
+pub fn device_address(device_id: u64) -> [u8; 6] {
+    let mut random = device_id & RANDOM_MASK;
+    // all zeros and all ones are not valid static addresses
+    if random == 0 || random == RANDOM_MASK {
+        random ^= 1;
+    }
+    let mut addr = [0; 6];
+    addr.copy_from_slice(&random.to_le_bytes()[..6]);
+    addr[5] |= 0b1100_0000;
+    addr
+}
+
+/// `base` with the last four hex digits of the address, e.g. `rcar-3F2A`
+///
+/// `base` is cut at [`NAME_MAX`] bytes, like `CarConfig::set_name` does
+pub fn unique_name<'a>(
+    base: &str,
+    device_id: u64,
+    buf: &'a mut [u8; UNIQUE_NAME_MAX],
+) -> &'a str {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let addr = device_address(device_id);
+    let base = &base.as_bytes()[..base.len().min(NAME_MAX)];
+    let (name, suffix) = buf.split_at_mut(base.len());
+    name.copy_from_slice(base);
+    suffix[0] = b'-';
+    for (i, byte) in [addr[1], addr[0]].into_iter().enumerate() {
+        suffix[1 + 2 * i] = HEX[(byte >> 4) as usize];
+        suffix[2 + 2 * i] = HEX[(byte & 0xf) as usize];
+    }
+    core::str::from_utf8(&buf[..base.len() + NAME_SUFFIX_LEN]).unwrap_or_default()
+}
+
+/// whether advertising or scan response data lists `RcCarService`
+pub fn advertises_service(data: &[u8]) -> bool {
+    const INCOMPLETE_128: u8 = 0x06;
+    const COMPLETE_128: u8 = 0x07;
+    let uuid = SERVICE_UUID.to_le_bytes();
+    let mut rest = data;
+    while let [len, kind, ..] = rest {
+        let len = *len as usize;
+        if len == 0 || rest.len() < len + 1 {
+            break;
+        }
+        if matches!(*kind, INCOMPLETE_128 | COMPLETE_128)
+            && rest[2..len + 1].chunks_exact(16).any(|u| u == uuid)
+        {
+            return true;
+        }
+        rest = &rest[len + 1..];
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_static_and_unique() {
+        let a = device_address(0x0123_4567_89ab_cdef);
+        assert_eq!(a, [0xef, 0xcd, 0xab, 0x89, 0x67, 0xc5]);
+        assert_ne!(a, device_address(0x0123_4567_89ab_cdee));
+        assert_eq!(device_address(0)[..5], [1, 0, 0, 0, 0]);
+        assert_eq!(device_address(u64::MAX)[0], 0xfe);
+    }
+
+    #[test]
+    fn names_end_in_the_address() {
+        let mut buf = [0; UNIQUE_NAME_MAX];
+        assert_eq!(unique_name("rcar", 0x3f2a, &mut buf), "rcar-3F2A");
+        let long = "a name of twenty one!";
+        assert_eq!(unique_name(long, 0, &mut buf).len(), UNIQUE_NAME_MAX);
+    }
+
+    #[test]
+    fn finds_the_service() {
+        let mut scan = vec![17, 0x07];
+        scan.extend_from_slice(&SERVICE_UUID.to_le_bytes());
+        let mut adv = vec![2, 0x01, 0x06, 5, 0x09];
+        adv.extend_from_slice(b"rcar");
+        assert!(advertises_service(&scan));
+        assert!(!advertises_service(&adv));
+        adv.extend_from_slice(&scan);
+        assert!(advertises_service(&adv));
+        // cut short
+        assert!(!advertises_service(&scan[..10]));
+    }
+}
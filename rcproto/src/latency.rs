@@ -0,0 +1,241 @@
+//! Round trip and loss of velocity commands
+//!
+//! The controller numbers and timestamps every [`VelocityCommand`], the car
+//! answers each one with an [`Echo`]. Timestamps are microseconds on the
+//! controller clock and wrap, the car only hands them back.
+//!
+//! [`VelocityCommand`]: crate::VelocityCommand
+
+use crate::{DecodeError, VelocityCommand};
+
+/// encoded size of an [`Echo`]
+pub const ECHO_LEN: usize = 2 + 4 + 2;
+/// encoded size of a [`LatencyReport`]
+pub const LATENCY_LEN: usize = 3 * 4 + 2 + 2;
+
+/// the latest command the car took, notified back to its sender
+///
+/// layout: `[seq: u16, sent_us: u32, received: u16]`, little endian
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Echo {
+    pub seq: u16,
+    pub sent_us: u32,
+    /// commands the car received from this client so far, wrapping
+    pub received: u16,
+}
+
+impl Echo {
+    pub fn of(cmd: &VelocityCommand, received: u16) -> Self {
+        Echo {
+            seq: cmd.seq,
+            sent_us: cmd.sent_us,
+            received,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; ECHO_LEN] {
+        let mut buf = [0; ECHO_LEN];
+        buf[0..2].copy_from_slice(&self.seq.to_le_bytes());
+        buf[2..6].copy_from_slice(&self.sent_us.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.received.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != ECHO_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        Ok(Echo {
+            seq: u16::from_le_bytes([buf[0], buf[1]]),
+            sent_us: u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]),
+            received: u16::from_le_bytes([buf[6], buf[7]]),
+        })
+    }
+}
+
+/// what the controller measured since its last report
+///
+/// layout: `[rtt_us: u32, jitter_us: u32, max_rtt_us: u32, sent: u16,
+/// lost: u16]`, little endian
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LatencyReport {
+    /// smoothed round trip
+    pub rtt_us: u32,
+    /// smoothed change of the round trip from one echo to the next
+    pub jitter_us: u32,
+    pub max_rtt_us: u32,
+    /// commands covered by echoes
+    pub sent: u16,
+    /// of those, the ones the car never got
+    pub lost: u16,
+}
+
+impl LatencyReport {
+    /// share of commands lost, in percent
+    pub fn loss_percent(&self) -> f32 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        self.lost as f32 * 100.0 / self.sent as f32
+    }
+
+    pub fn encode(&self) -> [u8; LATENCY_LEN] {
+        let mut buf = [0; LATENCY_LEN];
+        buf[0..4].copy_from_slice(&self.rtt_us.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.jitter_us.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.max_rtt_us.to_le_bytes());
+        buf[12..14].copy_from_slice(&self.sent.to_le_bytes());
+        buf[14..16].copy_from_slice(&self.lost.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != LATENCY_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        Ok(LatencyReport {
+            rtt_us: u32_at(0),
+            jitter_us: u32_at(4),
+            max_rtt_us: u32_at(8),
+            sent: u16::from_le_bytes([buf[12], buf[13]]),
+            lost: u16::from_le_bytes([buf[14], buf[15]]),
+        })
+    }
+}
+
+/// turns echoes into a [`LatencyReport`]
+///
+/// Round trip and jitter are smoothed like RTP does, by 1/8 and 1/16. Loss
+/// comes from comparing how far the sequence and the received count of the
+/// car moved between two echoes, so echoes that get lost or merged on their
+/// way back don't count as lost commands.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyStats {
+    rtt: Option<f32>,
+    jitter: f32,
+    last_rtt: Option<u32>,
+    last: Option<Echo>,
+    report: LatencyReport,
+}
+
+impl LatencyStats {
+    pub const fn new() -> Self {
+        LatencyStats {
+            rtt: None,
+            jitter: 0.0,
+            last_rtt: None,
+            last: None,
+            report: LatencyReport {
+                rtt_us: 0,
+                jitter_us: 0,
+                max_rtt_us: 0,
+                sent: 0,
+                lost: 0,
+            },
+        }
+    }
+
+    /// takes an echo that arrived at `now_us`, returns its round trip, or
+    /// `None` for a stale echo
+    pub fn echoed(&mut self, echo: &Echo, now_us: u32) -> Option<u32> {
+        if let Some(last) = self.last {
+            let sent = echo.seq.wrapping_sub(last.seq);
+            // repeated, or older than the last one
+            if sent == 0 || sent > u16::MAX / 2 {
+                return None;
+            }
+            let received = echo.received.wrapping_sub(last.received);
+            self.report.sent = self.report.sent.saturating_add(sent);
+            self.report.lost = self
+                .report
+                .lost
+                .saturating_add(sent.saturating_sub(received));
+        }
+        self.last = Some(*echo);
+
+        let rtt = now_us.wrapping_sub(echo.sent_us);
+        let smooth = match self.rtt {
+            Some(smooth) => smooth + (rtt as f32 - smooth) / 8.0,
+            None => rtt as f32,
+        };
+        self.rtt = Some(smooth);
+        if let Some(last_rtt) = self.last_rtt {
+            let change = rtt.abs_diff(last_rtt) as f32;
+            self.jitter += (change - self.jitter) / 16.0;
+        }
+        self.last_rtt = Some(rtt);
+        self.report.max_rtt_us = self.report.max_rtt_us.max(rtt);
+        Some(rtt)
+    }
+
+    /// the numbers since the last report, the smoothed ones carry on
+    pub fn report(&mut self) -> LatencyReport {
+        let report = LatencyReport {
+            rtt_us: self.rtt.unwrap_or(0.0) as u32,
+            jitter_us: self.jitter as u32,
+            ..self.report
+        };
+        self.report = LatencyReport::default();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_and_report_roundtrip() {
+        let mut cmd = VelocityCommand::new(0.0, 0.5, 0.0);
+        cmd.seq = 513;
+        cmd.sent_us = 0xdead_beef;
+        let echo = Echo::of(&cmd, 7);
+        assert_eq!(echo.encode(), [1, 2, 0xef, 0xbe, 0xad, 0xde, 7, 0]);
+        assert_eq!(Echo::decode(&echo.encode()), Ok(echo));
+        assert_eq!(Echo::decode(&[0; 3]), Err(DecodeError::Length(3)));
+
+        let report = LatencyReport {
+            rtt_us: 15_000,
+            jitter_us: 800,
+            max_rtt_us: 31_250,
+            sent: 40,
+            lost: 2,
+        };
+        assert_eq!(LatencyReport::decode(&report.encode()), Ok(report));
+        assert_eq!(report.loss_percent(), 5.0);
+    }
+
+    #[test]
+    fn counts_loss_and_jitter() {
+        let mut stats = LatencyStats::new();
+        let echo = |seq: u16, received: u16| Echo {
+            seq,
+            sent_us: (seq as u32).wrapping_mul(100_000),
+            received,
+        };
+        // the timestamps wrap between the first two echoes
+        let first = Echo {
+            sent_us: u32::MAX - 4_999,
+            ..echo(u16::MAX, 0)
+        };
+        assert_eq!(stats.echoed(&first, 5_000), Some(10_000));
+        assert_eq!(stats.echoed(&echo(0, 1), 20_000), Some(20_000));
+        // seq 1 and 2 got lost, the echo of 3 never came back
+        assert_eq!(stats.echoed(&echo(4, 3), 410_000), Some(10_000));
+        assert_eq!(stats.echoed(&echo(4, 3), 420_000), None);
+        assert_eq!(stats.echoed(&echo(2, 2), 430_000), None);
+
+        let report = stats.report();
+        assert_eq!((report.sent, report.lost), (5, 2));
+        assert_eq!(report.max_rtt_us, 20_000);
+        assert!(report.rtt_us > 10_000 && report.rtt_us < 20_000);
+        assert_eq!(report.jitter_us, 1_210);
+
+        let next = stats.report();
+        assert_eq!((next.sent, next.lost, next.max_rtt_us), (0, 0, 0));
+        assert_eq!(next.rtt_us, report.rtt_us);
+    }
+}
@@ -0,0 +1,799 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Wire protocol shared by the car (`rcar`) and the controller (`rctrl`).
+//!
+//! The gatt macros in `nrf-softdevice` only accept uuid literals, so the
+//! `#[gatt_service]`/`#[gatt_client]` attributes still spell them out.
+//! Keep those literals in sync with the constants here.
+//!
+//! Run the tests on the host, e.g.
+//! `cargo test -p rcproto --target x86_64-unknown-linux-gnu`
+
+mod config;
+mod control;
+mod identity;
+mod latency;
+mod link;
+mod script;
+mod storage;
+
+pub use config::*;
+pub use control::*;
+pub use identity::*;
+pub use latency::*;
+pub use link::*;
+pub use script::*;
+pub use storage::*;
+
+/// uuid of `RcCarService`, as used in the gatt attributes
+pub const SERVICE_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a30";
+/// uuid of `RcCarService`, as used in advertisement data
+pub const SERVICE_UUID: u128 = 0x8a8ec266_3ede_4a2f_a87b_aafbc55b8a30;
+/// 16 bit uuid of the target velocity characteristic
+pub const TARGET_VELOCITY_UUID: u16 = 0x2C09;
+/// uuid of the wheel speed controller gains characteristic
+pub const PID_GAINS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a31";
+/// uuid of the motion limits characteristic
+pub const MOTION_LIMITS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a32";
+/// uuid of the latched faults characteristic, a single [`Faults`] byte
+pub const FAULTS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a33";
+/// uuid of the failsafe timeout characteristic, a `u16` in milliseconds
+pub const FAILSAFE_TIMEOUT_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a34";
+/// uuid of the persistent car config characteristic
+pub const CONFIG_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a35";
+/// uuid of the zero heading characteristic, any write zeroes the heading
+pub const ZERO_HEADING_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a36";
+/// uuid of the odometry characteristic, notifies a [`Pose`], any write resets it
+pub const POSE_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a37";
+/// uuid of the motor error counters characteristic, see [`MotorErrors`]
+pub const MOTOR_ERRORS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a38";
+/// uuid of the motion script characteristic, takes a [`ScriptCommand`]
+pub const SCRIPT_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a39";
+/// uuid of the script progress characteristic, notifies a [`ScriptStatus`]
+pub const SCRIPT_STATUS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3a";
+/// uuid of the speed gear characteristic, a single [`Gear`] byte
+pub const GEAR_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3b";
+/// uuid of the obstacle distance characteristic, a `u16` in mm
+pub const DISTANCE_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3c";
+/// uuid of the line follow characteristic, see [`LineFollow`]
+pub const LINE_FOLLOW_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3d";
+/// uuid of the horn characteristic, any write honks
+pub const HORN_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3e";
+/// uuid of the emergency stop characteristic, a single [`EStopState`] byte
+pub const ESTOP_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a3f";
+/// uuid of the characteristic taking [`ControlRequest`]s
+pub const CONTROL_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a40";
+/// uuid of the characteristic carrying the [`ControlStatus`]
+pub const CONTROL_STATUS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a41";
+/// uuid of the characteristic carrying the [`LinkParams`] of the connection
+pub const LINK_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a42";
+/// uuid of the characteristic notifying an [`Echo`] of every velocity command
+pub const ECHO_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a43";
+/// uuid of the characteristic carrying the controller's [`LatencyReport`]
+pub const LATENCY_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a44";
+/// uuid of the compass characteristic, a single [`CompassState`] byte
+pub const COMPASS_UUID_STR: &str = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a45";
+
+/// default time without velocity commands before the car stops itself
+pub const FAILSAFE_TIMEOUT_MS: u16 = 500;
+/// how often the controller repeats an unchanged command, well below the timeout
+pub const KEEPALIVE_MS: u16 = 100;
+/// shortest failsafe timeout, one late keepalive must not stop the car
+pub const MIN_FAILSAFE_TIMEOUT_MS: u16 = 2 * KEEPALIVE_MS;
+/// longest failsafe timeout, a car that lost its controller stops within it
+pub const MAX_FAILSAFE_TIMEOUT_MS: u16 = 5000;
+
+/// obstacle distance when nothing is in range of the sensor
+pub const NO_OBSTACLE: u16 = u16::MAX;
+
+/// bumped whenever the layout of a command changes
+pub const VERSION: u8 = 2;
+
+/// encoded size of a [`VelocityCommand`]
+pub const VELOCITY_LEN: usize = 2 + 2 + 4 + 3 * 4;
+
+/// a failsafe timeout written by a client, if it lies within
+/// [`MIN_FAILSAFE_TIMEOUT_MS`] and [`MAX_FAILSAFE_TIMEOUT_MS`]
+pub fn failsafe_timeout(ms: u16) -> Result<u16, DecodeError> {
+    if (MIN_FAILSAFE_TIMEOUT_MS..=MAX_FAILSAFE_TIMEOUT_MS).contains(&ms) {
+        Ok(ms)
+    } else {
+        Err(DecodeError::Invalid)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    /// the buffer is not exactly as long as the command
+    Length(usize),
+    /// the peer speaks another version of the protocol
+    Version(u8),
+    /// a float is NaN or infinite
+    NotFinite,
+    /// a field holds a value the car can't use
+    Invalid,
+}
+
+/// a set of bits on the wire
+macro_rules! bitset {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        pub struct $name(u8);
+
+        impl $name {
+            pub const fn empty() -> Self {
+                $name(0)
+            }
+            pub const fn from_bits(bits: u8) -> Self {
+                $name(bits)
+            }
+            pub const fn bits(self) -> u8 {
+                self.0
+            }
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+            pub const fn contains(self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+            pub fn insert(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+            pub fn remove(&mut self, other: $name) {
+                self.0 &= !other.0;
+            }
+        }
+    };
+}
+
+bitset!(
+    /// option bits sent along with every [`VelocityCommand`]
+    Flags
+);
+
+impl Flags {
+    /// `x` and `y` are in the world frame set by zeroing the heading
+    pub const HEADLESS: Flags = Flags(1 << 0);
+}
+
+bitset!(
+    /// latched faults of the car, they stay set until the client clears them
+    Faults
+);
+
+impl Faults {
+    /// no velocity command arrived within the failsafe timeout
+    pub const COMMAND_TIMEOUT: Faults = Faults(1 << 0);
+    /// the connection to the controller dropped while driving
+    pub const LINK_LOST: Faults = Faults(1 << 1);
+    /// the motor board stopped taking outputs, even after a bus recovery
+    pub const MOTOR_BOARD_LOST: Faults = Faults(1 << 2);
+    /// the pack dropped below the cutoff voltage, the motors are off
+    pub const BATTERY_CUTOFF: Faults = Faults(1 << 3);
+}
+
+/// normalized body velocity, every component in -1.0..=1.0
+///
+/// layout: `[version, flags, seq: u16, sent_us: u32, x: f32, y: f32, z: f32]`,
+/// little endian
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VelocityCommand {
+    pub flags: Flags,
+    /// counts up with every command the controller sends, see [`Echo`]
+    pub seq: u16,
+    /// when the controller sent it, in microseconds of its own clock
+    pub sent_us: u32,
+    /// strafe, positive to the right
+    pub x: f32,
+    /// forward
+    pub y: f32,
+    /// rotation
+    pub z: f32,
+}
+
+impl VelocityCommand {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        VelocityCommand {
+            flags: Flags::empty(),
+            seq: 0,
+            sent_us: 0,
+            x,
+            y,
+            z,
+        }
+    }
+
+    pub fn to_array(&self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// the same command with every component times `factor`
+    pub fn scaled(&self, factor: f32) -> Self {
+        VelocityCommand {
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+            ..*self
+        }
+    }
+
+    pub fn encode(&self) -> [u8; VELOCITY_LEN] {
+        let mut buf = [0; VELOCITY_LEN];
+        buf[0] = VERSION;
+        buf[1] = self.flags.bits();
+        buf[2..4].copy_from_slice(&self.seq.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.sent_us.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.x.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.y.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.z.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != VELOCITY_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        if buf[0] != VERSION {
+            return Err(DecodeError::Version(buf[0]));
+        }
+        let x = read_f32(&buf[8..12]);
+        let y = read_f32(&buf[12..16]);
+        let z = read_f32(&buf[16..20]);
+        if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+            return Err(DecodeError::NotFinite);
+        }
+        // everything downstream takes normalized components
+        if [x, y, z].iter().any(|v| !(-1.0..=1.0).contains(v)) {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(VelocityCommand {
+            flags: Flags::from_bits(buf[1]),
+            seq: u16::from_le_bytes([buf[2], buf[3]]),
+            sent_us: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            x,
+            y,
+            z,
+        })
+    }
+}
+
+/// state of the emergency stop, writing one asks the car to go there
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EStopState {
+    /// the car takes velocity commands
+    #[default]
+    Armed = 0,
+    /// the car ignores velocity commands until re-armed with the joystick centered
+    Stopped = 1,
+}
+
+impl TryFrom<u8> for EStopState {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(EStopState::Armed),
+            1 => Ok(EStopState::Stopped),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// state of the compass calibration, writing [`CompassState::Calibrating`]
+/// spins the car in place until it has one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CompassState {
+    /// no headless driving, the heading can't be trusted
+    #[default]
+    Uncalibrated = 0,
+    Calibrating = 1,
+    Calibrated = 2,
+}
+
+impl TryFrom<u8> for CompassState {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(CompassState::Uncalibrated),
+            1 => Ok(CompassState::Calibrating),
+            2 => Ok(CompassState::Calibrated),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// share of full speed left by a [`Gear`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GearScale {
+    /// of `x` and `y`
+    pub translate: f32,
+    /// of `z`
+    pub rotate: f32,
+}
+
+/// speed gear of the car, scales every velocity command it drives with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gear {
+    /// for first timers
+    Crawl = 0,
+    #[default]
+    Normal = 1,
+    Turbo = 2,
+}
+
+impl TryFrom<u8> for Gear {
+    type Error = DecodeError;
+
+    fn try_from(v: u8) -> Result<Self, DecodeError> {
+        match v {
+            0 => Ok(Gear::Crawl),
+            1 => Ok(Gear::Normal),
+            2 => Ok(Gear::Turbo),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+impl Gear {
+    /// the low gears keep more of the rotation, a car that barely turns is
+    /// hard to steer
+    pub const fn scale(self) -> GearScale {
+        match self {
+            Gear::Crawl => GearScale {
+                translate: 0.25,
+                rotate: 0.4,
+            },
+            Gear::Normal => GearScale {
+                translate: 0.6,
+                rotate: 0.7,
+            },
+            Gear::Turbo => GearScale {
+                translate: 1.0,
+                rotate: 1.0,
+            },
+        }
+    }
+
+    /// `cmd` scaled down to this gear
+    pub fn apply(self, cmd: &VelocityCommand) -> VelocityCommand {
+        let scale = self.scale();
+        VelocityCommand {
+            x: cmd.x * scale.translate,
+            y: cmd.y * scale.translate,
+            z: cmd.z * scale.rotate,
+            ..*cmd
+        }
+    }
+}
+
+/// encoded size of [`PidGains`]
+pub const PID_GAINS_LEN: usize = 3 * 4;
+
+/// gains of the per wheel speed controller
+///
+/// layout: `[kp: f32, ki: f32, kd: f32]`, little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Default for PidGains {
+    fn default() -> Self {
+        PidGains {
+            kp: 0.5,
+            ki: 1.5,
+            kd: 0.0,
+        }
+    }
+}
+
+impl PidGains {
+    pub fn encode(&self) -> [u8; PID_GAINS_LEN] {
+        let mut buf = [0; PID_GAINS_LEN];
+        write_f32s(&mut buf, &[self.kp, self.ki, self.kd]);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let [kp, ki, kd] = read_f32s(buf)?;
+        Ok(PidGains { kp, ki, kd })
+    }
+}
+
+/// encoded size of [`MotionLimits`]
+pub const MOTION_LIMITS_LEN: usize = 6 * 4;
+
+/// rate limits of one axis, in normalized speed per second (squared)
+///
+/// a limit that is not positive is not enforced
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AxisLimits {
+    /// acceleration while speeding up
+    pub accel: f32,
+    /// acceleration while braking
+    pub decel: f32,
+    pub jerk: f32,
+}
+
+/// limits of the motion profile between the joystick and the wheels
+///
+/// layout: `[linear: AxisLimits, angular: AxisLimits]`, each
+/// `[accel: f32, decel: f32, jerk: f32]` little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MotionLimits {
+    /// strafe and forward
+    pub linear: AxisLimits,
+    /// rotation
+    pub angular: AxisLimits,
+}
+
+impl Default for MotionLimits {
+    fn default() -> Self {
+        MotionLimits {
+            linear: AxisLimits {
+                accel: 2.0,
+                decel: 4.0,
+                jerk: 20.0,
+            },
+            angular: AxisLimits {
+                accel: 4.0,
+                decel: 6.0,
+                jerk: 40.0,
+            },
+        }
+    }
+}
+
+impl MotionLimits {
+    pub fn encode(&self) -> [u8; MOTION_LIMITS_LEN] {
+        let (l, a) = (&self.linear, &self.angular);
+        let mut buf = [0; MOTION_LIMITS_LEN];
+        write_f32s(
+            &mut buf,
+            &[l.accel, l.decel, l.jerk, a.accel, a.decel, a.jerk],
+        );
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let [la, ld, lj, aa, ad, aj] = read_f32s(buf)?;
+        Ok(MotionLimits {
+            linear: AxisLimits {
+                accel: la,
+                decel: ld,
+                jerk: lj,
+            },
+            angular: AxisLimits {
+                accel: aa,
+                decel: ad,
+                jerk: aj,
+            },
+        })
+    }
+}
+
+/// encoded size of [`Pose`]
+pub const POSE_LEN: usize = 3 * 4;
+
+/// dead reckoned pose of the car, relative to where it was last reset
+///
+/// layout: `[x: f32, y: f32, heading: f32]`, little endian. `x` is to the
+/// right and `y` forward of the starting pose in meters, `heading` is in
+/// radians counterclockwise
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+impl Pose {
+    pub fn encode(&self) -> [u8; POSE_LEN] {
+        let mut buf = [0; POSE_LEN];
+        write_f32s(&mut buf, &[self.x, self.y, self.heading]);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let [x, y, heading] = read_f32s(buf)?;
+        Ok(Pose { x, y, heading })
+    }
+}
+
+/// encoded size of [`MotorErrors`]
+pub const MOTOR_ERRORS_LEN: usize = 4 * 2;
+
+/// failed motor outputs since boot, after retries
+///
+/// layout: `[u16; 4]` little endian, front left, front right, back left and
+/// back right, in the order the outputs are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MotorErrors(pub [u16; 4]);
+
+impl MotorErrors {
+    pub fn encode(&self) -> [u8; MOTOR_ERRORS_LEN] {
+        let mut buf = [0; MOTOR_ERRORS_LEN];
+        for (chunk, count) in buf.chunks_exact_mut(2).zip(self.0) {
+            chunk.copy_from_slice(&count.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != MOTOR_ERRORS_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let mut counts = [0; 4];
+        for (count, chunk) in counts.iter_mut().zip(buf.chunks_exact(2)) {
+            *count = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(MotorErrors(counts))
+    }
+}
+
+/// encoded size of [`LineFollow`]
+pub const LINE_FOLLOW_LEN: usize = 2 + 3 * 4;
+/// most IR sensors a line follower reads
+pub const LINE_SENSORS_MAX: u8 = 5;
+
+/// the line follow mode, see `rcdrive::line`
+///
+/// layout: `[enabled, sensors, speed: f32, kp: f32, kd: f32]`, little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineFollow {
+    /// the car follows the line instead of the joystick
+    pub enabled: bool,
+    /// IR sensors in use, 2 to [`LINE_SENSORS_MAX`]
+    pub sensors: u8,
+    /// forward speed on a straight line, 0.0 to 1.0
+    pub speed: f32,
+    /// steering per offset of the line from the center
+    pub kp: f32,
+    /// steering per change of the offset, damps the weaving
+    pub kd: f32,
+}
+
+impl Default for LineFollow {
+    fn default() -> Self {
+        LineFollow {
+            enabled: false,
+            sensors: 2,
+            speed: 0.3,
+            kp: 1.0,
+            kd: 0.05,
+        }
+    }
+}
+
+impl LineFollow {
+    pub fn encode(&self) -> [u8; LINE_FOLLOW_LEN] {
+        let mut buf = [0; LINE_FOLLOW_LEN];
+        buf[0] = self.enabled as u8;
+        buf[1] = self.sensors;
+        write_f32s(&mut buf[2..], &[self.speed, self.kp, self.kd]);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != LINE_FOLLOW_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let [speed, kp, kd] = read_f32s(&buf[2..])?;
+        if buf[0] > 1 || !(2..=LINE_SENSORS_MAX).contains(&buf[1]) || !(0.0..=1.0).contains(&speed)
+        {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(LineFollow {
+            enabled: buf[0] == 1,
+            sensors: buf[1],
+            speed,
+            kp,
+            kd,
+        })
+    }
+}
+
+pub(crate) fn read_f32(bytes: &[u8]) -> f32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(bytes);
+    f32::from_le_bytes(raw)
+}
+
+pub(crate) fn write_f32s(buf: &mut [u8], values: &[f32]) {
+    for (chunk, v) in buf.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// reads a buffer made up of exactly `N` finite floats
+pub(crate) fn read_f32s<const N: usize>(buf: &[u8]) -> Result<[f32; N], DecodeError> {
+    if buf.len() != N * 4 {
+        return Err(DecodeError::Length(buf.len()));
+    }
+    let mut values = [0.0; N];
+    for (v, chunk) in values.iter_mut().zip(buf.chunks_exact(4)) {
+        *v = read_f32(chunk);
+    }
+    if values.iter().any(|v| !v.is_finite()) {
+        return Err(DecodeError::NotFinite);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid_forms_agree() {
+        let hex: String = SERVICE_UUID_STR.chars().filter(|c| *c != '-').collect();
+        assert_eq!(u128::from_str_radix(&hex, 16).unwrap(), SERVICE_UUID);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut cmd = VelocityCommand::new(0.25, -1.0, 0.5);
+        cmd.flags = Flags::from_bits(0b101);
+        cmd.seq = 0x0102;
+        cmd.sent_us = 123_456;
+        let buf = cmd.encode();
+        assert_eq!(buf[0], VERSION);
+        assert_eq!(buf[1], 0b101);
+        assert_eq!(&buf[2..4], &[0x02, 0x01]);
+        assert_eq!(VelocityCommand::decode(&buf), Ok(cmd));
+    }
+
+    #[test]
+    fn layout_is_little_endian() {
+        let buf = VelocityCommand::new(1.0, 0.0, -2.0).encode();
+        assert_eq!(&buf[2..8], &[0; 6]);
+        assert_eq!(&buf[8..12], &[0x00, 0x00, 0x80, 0x3f]);
+        assert_eq!(&buf[12..16], &[0; 4]);
+        assert_eq!(&buf[16..20], &[0x00, 0x00, 0x00, 0xc0]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let buf = VelocityCommand::default().encode();
+        assert_eq!(
+            VelocityCommand::decode(&buf[..12]),
+            Err(DecodeError::Length(12))
+        );
+
+        let mut old = buf;
+        old[0] = 0;
+        assert_eq!(VelocityCommand::decode(&old), Err(DecodeError::Version(0)));
+
+        let nan = VelocityCommand::new(f32::NAN, 0.0, 0.0).encode();
+        assert_eq!(VelocityCommand::decode(&nan), Err(DecodeError::NotFinite));
+    }
+
+    #[test]
+    fn rejects_unnormalized() {
+        let full = VelocityCommand::new(-1.0, 1.0, 1.0);
+        assert_eq!(VelocityCommand::decode(&full.encode()), Ok(full));
+        for cmd in [
+            VelocityCommand::new(1.01, 0.0, 0.0),
+            VelocityCommand::new(0.0, -2.0, 0.0),
+            VelocityCommand::new(0.0, 0.0, 1e9),
+        ] {
+            assert_eq!(
+                VelocityCommand::decode(&cmd.encode()),
+                Err(DecodeError::Invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn failsafe_timeout_range() {
+        for ms in [
+            MIN_FAILSAFE_TIMEOUT_MS,
+            FAILSAFE_TIMEOUT_MS,
+            MAX_FAILSAFE_TIMEOUT_MS,
+        ] {
+            assert_eq!(failsafe_timeout(ms), Ok(ms));
+        }
+        for ms in [0, KEEPALIVE_MS, MAX_FAILSAFE_TIMEOUT_MS + 1, u16::MAX] {
+            assert_eq!(failsafe_timeout(ms), Err(DecodeError::Invalid));
+        }
+    }
+
+    #[test]
+    fn gears() {
+        assert_eq!(Gear::try_from(Gear::Turbo as u8), Ok(Gear::Turbo));
+        assert_eq!(Gear::try_from(3), Err(DecodeError::Invalid));
+
+        let mut cmd = VelocityCommand::new(1.0, -0.5, 1.0);
+        cmd.flags = Flags::HEADLESS;
+        let crawl = Gear::Crawl.apply(&cmd);
+        assert_eq!(crawl.to_array(), [0.25, -0.125, 0.4]);
+        assert_eq!(crawl.flags, Flags::HEADLESS);
+        assert_eq!(Gear::Turbo.apply(&cmd), cmd);
+    }
+
+    #[test]
+    fn gains_roundtrip() {
+        let gains = PidGains {
+            kp: 1.0,
+            ki: 0.25,
+            kd: -0.5,
+        };
+        assert_eq!(PidGains::decode(&gains.encode()), Ok(gains));
+        assert_eq!(PidGains::decode(&[0; 4]), Err(DecodeError::Length(4)));
+    }
+
+    #[test]
+    fn limits_roundtrip() {
+        let mut limits = MotionLimits::default();
+        limits.angular.jerk = 0.0;
+        let buf = limits.encode();
+        assert_eq!(&buf[12..16], &limits.angular.accel.to_le_bytes());
+        assert_eq!(MotionLimits::decode(&buf), Ok(limits));
+    }
+
+    #[test]
+    fn pose_roundtrip() {
+        let pose = Pose {
+            x: 1.5,
+            y: -0.25,
+            heading: 3.0,
+        };
+        let buf = pose.encode();
+        assert_eq!(&buf[8..12], &3.0f32.to_le_bytes());
+        assert_eq!(Pose::decode(&buf), Ok(pose));
+    }
+
+    #[test]
+    fn motor_errors_roundtrip() {
+        let errors = MotorErrors([0, 1, 0x1234, u16::MAX]);
+        let buf = errors.encode();
+        assert_eq!(&buf[4..6], &[0x34, 0x12]);
+        assert_eq!(MotorErrors::decode(&buf), Ok(errors));
+    }
+
+    #[test]
+    fn line_follow_roundtrip() {
+        let line = LineFollow {
+            enabled: true,
+            sensors: 5,
+            ..LineFollow::default()
+        };
+        let buf = line.encode();
+        assert_eq!(&buf[..2], &[1, 5]);
+        assert_eq!(LineFollow::decode(&buf), Ok(line));
+
+        let mut one_sensor = buf;
+        one_sensor[1] = 1;
+        assert_eq!(LineFollow::decode(&one_sensor), Err(DecodeError::Invalid));
+    }
+
+    #[test]
+    fn flags() {
+        let a = Flags::from_bits(0b01);
+        let b = Flags::from_bits(0b10);
+        let mut f = Flags::empty();
+        f.insert(a);
+        assert!(f.contains(a));
+        assert!(!f.contains(b));
+        f.insert(b);
+        f.remove(a);
+        assert_eq!(f, b);
+    }
+}
@@ -0,0 +1,157 @@
+//! What both ends ask of a connection, and what it ended up with
+//!
+//! Intervals are in units of 1.25ms and the supervision timeout in units of
+//! 10ms, as the softdevice takes them.
+
+use crate::DecodeError;
+
+/// encoded size of [`LinkParams`]
+pub const LINK_PARAMS_LEN: usize = 2 + 2 + 2 + 1 + 2;
+
+/// connection intervals to ask for, fastest first, the next one is tried
+/// when the peer doesn't go along
+pub const INTERVAL_STEPS: [(u16, u16); 3] = [(6, 6), (6, 12), (12, 24)];
+/// every connection event is used, so commands never wait for one
+pub const SLAVE_LATENCY: u16 = 0;
+/// a dead link is noticed well within the failsafe timeout
+pub const SUPERVISION_TIMEOUT: u16 = 40;
+/// longest packet data length extension allows, in bytes
+pub const DATA_LENGTH_MAX: u16 = 251;
+/// packet length without data length extension, in bytes
+pub const DATA_LENGTH_DEFAULT: u16 = 27;
+/// packet length when the softdevice doesn't tell what was negotiated
+pub const DATA_LENGTH_UNKNOWN: u16 = 0;
+
+/// radio rate of a connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkPhy {
+    /// asked for, but the softdevice doesn't tell what was negotiated
+    Unknown = 0,
+    #[default]
+    M1 = 1,
+    M2 = 2,
+}
+
+impl TryFrom<u8> for LinkPhy {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, DecodeError> {
+        match value {
+            0 => Ok(LinkPhy::Unknown),
+            1 => Ok(LinkPhy::M1),
+            2 => Ok(LinkPhy::M2),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// the parameters a connection runs with
+///
+/// layout: `[interval: u16, latency: u16, timeout: u16, phy, data_length: u16]`,
+/// little endian
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkParams {
+    /// in units of 1.25ms
+    pub interval: u16,
+    /// connection events the peripheral may skip
+    pub latency: u16,
+    /// supervision timeout in units of 10ms
+    pub timeout: u16,
+    pub phy: LinkPhy,
+    /// longest packet in bytes, [`DATA_LENGTH_UNKNOWN`] if not known
+    pub data_length: u16,
+}
+
+impl Default for LinkParams {
+    /// what a connection starts out with, until something is negotiated
+    fn default() -> Self {
+        LinkParams {
+            interval: INTERVAL_STEPS[INTERVAL_STEPS.len() - 1].1,
+            latency: SLAVE_LATENCY,
+            timeout: SUPERVISION_TIMEOUT,
+            phy: LinkPhy::M1,
+            data_length: DATA_LENGTH_DEFAULT,
+        }
+    }
+}
+
+impl LinkParams {
+    pub fn interval_us(&self) -> u32 {
+        self.interval as u32 * 1250
+    }
+
+    /// whether the interval is within the step `(min, max)`
+    pub fn meets(&self, (min, max): (u16, u16)) -> bool {
+        (min..=max).contains(&self.interval) && self.latency == SLAVE_LATENCY
+    }
+
+    pub fn encode(&self) -> [u8; LINK_PARAMS_LEN] {
+        let mut buf = [0; LINK_PARAMS_LEN];
+        buf[0..2].copy_from_slice(&self.interval.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.latency.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.timeout.to_le_bytes());
+        buf[6] = self.phy as u8;
+        buf[7..9].copy_from_slice(&self.data_length.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != LINK_PARAMS_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        Ok(LinkParams {
+            interval: u16::from_le_bytes([buf[0], buf[1]]),
+            latency: u16::from_le_bytes([buf[2], buf[3]]),
+            timeout: u16::from_le_bytes([buf[4], buf[5]]),
+            phy: buf[6].try_into()?,
+            data_length: u16::from_le_bytes([buf[7], buf[8]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_roundtrip() {
+        let link = LinkParams {
+            interval: 6,
+            phy: LinkPhy::M2,
+            data_length: DATA_LENGTH_MAX,
+            ..LinkParams::default()
+        };
+        assert_eq!(link.interval_us(), 7500);
+        assert_eq!(LinkParams::decode(&link.encode()), Ok(link));
+
+        let unknown = LinkParams {
+            phy: LinkPhy::Unknown,
+            data_length: DATA_LENGTH_UNKNOWN,
+            ..link
+        };
+        assert_eq!(LinkParams::decode(&unknown.encode()), Ok(unknown));
+
+        let mut buf = link.encode();
+        buf[6] = 3;
+        assert_eq!(LinkParams::decode(&buf), Err(DecodeError::Invalid));
+    }
+
+    #[test]
+    fn steps_fall_back() {
+        let fast = LinkParams {
+            interval: 6,
+            ..LinkParams::default()
+        };
+        assert!(fast.meets(INTERVAL_STEPS[0]));
+        let slow = LinkParams {
+            interval: 24,
+            ..LinkParams::default()
+        };
+        let met = INTERVAL_STEPS.iter().position(|s| slow.meets(*s));
+        assert_eq!(met, Some(2));
+        let lazy = LinkParams { latency: 4, ..fast };
+        assert!(!lazy.meets(INTERVAL_STEPS[0]));
+    }
+}
@@ -0,0 +1,183 @@
+//! Motion scripts, uploaded to the car and run on its own timer
+
+use crate::{read_f32s, write_f32s, DecodeError};
+
+/// most segments the car keeps
+pub const SCRIPT_MAX: usize = 32;
+/// encoded size of a [`Segment`]
+pub const SEGMENT_LEN: usize = 3 * 4 + 2;
+/// most segments in a single [`ScriptCommand::Append`], fits the att mtu
+pub const APPEND_MAX: usize = 8;
+/// longest encoded [`ScriptCommand`]
+pub const SCRIPT_COMMAND_MAX: usize = 1 + APPEND_MAX * SEGMENT_LEN;
+/// encoded size of [`ScriptStatus`]
+pub const SCRIPT_STATUS_LEN: usize = 3;
+
+/// drive with a normalized body velocity for a while
+///
+/// layout: `[vx: f32, vy: f32, wz: f32, duration_ms: u16]`, little endian
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Segment {
+    pub vx: f32,
+    pub vy: f32,
+    pub wz: f32,
+    pub duration_ms: u16,
+}
+
+impl Segment {
+    pub fn encode(&self) -> [u8; SEGMENT_LEN] {
+        let mut buf = [0; SEGMENT_LEN];
+        write_f32s(&mut buf[..12], &[self.vx, self.vy, self.wz]);
+        buf[12..].copy_from_slice(&self.duration_ms.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != SEGMENT_LEN {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        let [vx, vy, wz] = read_f32s(&buf[..12])?;
+        Ok(Segment {
+            vx,
+            vy,
+            wz,
+            duration_ms: u16::from_le_bytes([buf[12], buf[13]]),
+        })
+    }
+}
+
+/// a write to the script characteristic
+///
+/// layout: `[op, segments: [Segment]]`, only `Append` carries segments
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScriptCommand<'a> {
+    /// aborts a running script and drops all segments
+    Clear,
+    /// adds encoded segments to the end, checked by `decode`
+    Append(&'a [u8]),
+    /// runs the script from the start, or resumes it when paused
+    Start,
+    /// holds the car still, the script keeps control
+    Pause,
+    /// stops the script and hands the car back to the joystick
+    Abort,
+}
+
+impl<'a> ScriptCommand<'a> {
+    /// the segments of an `Append`
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + 'a {
+        let bytes: &'a [u8] = match self {
+            ScriptCommand::Append(bytes) => bytes,
+            _ => &[],
+        };
+        // every chunk was checked by decode
+        bytes
+            .chunks_exact(SEGMENT_LEN)
+            .map(|chunk| Segment::decode(chunk).unwrap_or_default())
+    }
+
+    pub fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        let (&op, payload) = buf.split_first().ok_or(DecodeError::Length(0))?;
+        let command = match op {
+            0 => ScriptCommand::Clear,
+            1 => ScriptCommand::Append(payload),
+            2 => ScriptCommand::Start,
+            3 => ScriptCommand::Pause,
+            4 => ScriptCommand::Abort,
+            _ => return Err(DecodeError::Invalid),
+        };
+        let segments = payload.len() / SEGMENT_LEN;
+        let fits = match command {
+            ScriptCommand::Append(_) => {
+                payload.len() % SEGMENT_LEN == 0 && (1..=APPEND_MAX).contains(&segments)
+            }
+            _ => payload.is_empty(),
+        };
+        if !fits {
+            return Err(DecodeError::Length(buf.len()));
+        }
+        for chunk in payload.chunks_exact(SEGMENT_LEN) {
+            Segment::decode(chunk)?;
+        }
+        Ok(command)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScriptState {
+    /// nothing started since the last clear
+    #[default]
+    Idle = 0,
+    Running = 1,
+    Paused = 2,
+    /// ran through every segment
+    Done = 3,
+    Aborted = 4,
+}
+
+/// progress of the script on the car
+///
+/// layout: `[state, len, index]`, `index` is the segment being driven
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScriptStatus {
+    pub state: ScriptState,
+    pub len: u8,
+    pub index: u8,
+}
+
+impl ScriptStatus {
+    pub fn encode(&self) -> [u8; SCRIPT_STATUS_LEN] {
+        [self.state as u8, self.len, self.index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(duration_ms: u16) -> Segment {
+        Segment {
+            vx: 0.5,
+            vy: -0.25,
+            wz: 0.0,
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn segment_roundtrip() {
+        let buf = segment(1500).encode();
+        assert_eq!(&buf[12..], &1500u16.to_le_bytes());
+        assert_eq!(Segment::decode(&buf), Ok(segment(1500)));
+    }
+
+    #[test]
+    fn append_carries_segments() {
+        let mut buf = [1; 1 + 2 * SEGMENT_LEN];
+        buf[1..15].copy_from_slice(&segment(100).encode());
+        buf[15..].copy_from_slice(&segment(200).encode());
+        let command = ScriptCommand::decode(&buf).unwrap();
+        let durations: Vec<u16> = command.segments().map(|s| s.duration_ms).collect();
+        assert_eq!(durations, [100, 200]);
+        assert_eq!(ScriptCommand::decode(&[2]), Ok(ScriptCommand::Start));
+    }
+
+    #[test]
+    fn commands_reject_bad_input() {
+        assert_eq!(ScriptCommand::decode(&[]), Err(DecodeError::Length(0)));
+        assert_eq!(ScriptCommand::decode(&[9]), Err(DecodeError::Invalid));
+        assert_eq!(ScriptCommand::decode(&[1]), Err(DecodeError::Length(1)));
+        assert_eq!(ScriptCommand::decode(&[4, 0]), Err(DecodeError::Length(2)));
+
+        let too_many = [1; SCRIPT_COMMAND_MAX + SEGMENT_LEN];
+        assert!(ScriptCommand::decode(&too_many).is_err());
+
+        let mut nan = [1; 1 + SEGMENT_LEN];
+        nan[1..5].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert_eq!(ScriptCommand::decode(&nan), Err(DecodeError::NotFinite));
+    }
+}
@@ -0,0 +1,107 @@
+//! Records the firmwares keep in their internal flash
+//!
+//! A record is a magic, a body and the checksum of both, padded to whole
+//! flash words. An erased page or a write that was cut short reads as no
+//! record.
+
+/// size of a record around a body of `body_len` bytes
+pub const fn record_len(body_len: usize) -> usize {
+    (4 + body_len + 4).div_ceil(4) * 4
+}
+
+/// why a record can't be used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordError {
+    /// nothing was ever stored, or something else
+    Missing,
+    /// the write was cut short
+    Checksum,
+}
+
+/// FNV-1a, enough to catch a write that was cut short
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// `body` behind `magic` and ahead of the checksum, padded with erased bytes
+pub fn frame<const N: usize>(magic: [u8; 4], body: &[u8]) -> [u8; N] {
+    assert!(N == record_len(body.len()));
+    let mut record = [0xff; N];
+    let end = 4 + body.len();
+    record[..4].copy_from_slice(&magic);
+    record[4..end].copy_from_slice(body);
+    let sum = checksum(&record[..end]);
+    record[end..end + 4].copy_from_slice(&sum.to_le_bytes());
+    record
+}
+
+/// the body of `body_len` bytes in a record framed with `magic`
+pub fn unframe(magic: [u8; 4], record: &[u8], body_len: usize) -> Result<&[u8], RecordError> {
+    let end = 4 + body_len;
+    if record.len() < end + 4 || record[..4] != magic {
+        return Err(RecordError::Missing);
+    }
+    if record[end..end + 4] != checksum(&record[..end]).to_le_bytes() {
+        return Err(RecordError::Checksum);
+    }
+    Ok(&record[4..end])
+}
+
+/// `len` bytes of the internal flash at `address`
+///
+/// # Safety
+///
+/// `address..address + len` must be within the internal flash.
+pub unsafe fn read_flash(address: u32, len: usize) -> &'static [u8] {
+    // flash is memory mapped, reading it needs neither the NVMC nor the softdevice
+    core::slice::from_raw_parts(address as *const u8, len)
+}
+
+/// the `nrf_softdevice::ble::AddressType` of a peer stored as a `u8`, if any
+///
+/// A macro as rcproto doesn't depend on the softdevice, it expands in the
+/// firmwares, which do.
+#[macro_export]
+macro_rules! address_type {
+    ($raw:expr) => {{
+        use ::nrf_softdevice::ble::AddressType;
+        let raw: u8 = $raw;
+        [
+            AddressType::Public,
+            AddressType::RandomStatic,
+            AddressType::RandomPrivateResolvable,
+            AddressType::RandomPrivateNonResolvable,
+        ]
+        .into_iter()
+        .find(|t| *t as u8 == raw)
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; 4] = *b"TEST";
+
+    #[test]
+    fn record_roundtrip() {
+        let record: [u8; record_len(3)] = frame(MAGIC, &[1, 2, 3]);
+        assert_eq!(record.len(), 12);
+        assert_eq!(unframe(MAGIC, &record, 3), Ok(&[1, 2, 3][..]));
+        // the padding stays erased
+        let record: [u8; record_len(2)] = frame(MAGIC, &[1, 2]);
+        assert_eq!(record[10..], [0xff, 0xff]);
+    }
+
+    #[test]
+    fn damaged_record_is_refused() {
+        let mut record: [u8; record_len(3)] = frame(MAGIC, &[1, 2, 3]);
+        assert_eq!(unframe(*b"ELSE", &record, 3), Err(RecordError::Missing));
+        assert_eq!(unframe(MAGIC, &[0xff; 12], 3), Err(RecordError::Missing));
+        record[5] = 0xff;
+        assert_eq!(unframe(MAGIC, &record, 3), Err(RecordError::Checksum));
+    }
+}
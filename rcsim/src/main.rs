@@ -0,0 +1,42 @@
+//! Host simulator of `rcar`
+//!
+//! Feeds a scripted command stream through the control path of the car:
+//! command decode and the `rcdrive::Pipeline` of the motor task, written to
+//! a Wukong board on a mock i2c bus. A chassis pushed around by its wheels
+//! drives on whatever the board received, and the trajectory is printed as
+//! csv.
+//!
+//! It runs on the host, e.g.
+//! `cargo run --target x86_64-unknown-linux-gnu -- scripts/square.txt > square.csv`
+//! The script is read from stdin when no file is given.
+
+use std::{env, fs, io, process::ExitCode};
+
+mod physics;
+mod script;
+mod sim;
+
+fn main() -> ExitCode {
+    let text = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(&path).map_err(|e| format!("{path}: {e}")),
+        None => io::read_to_string(io::stdin()).map_err(|e| format!("stdin: {e}")),
+    };
+    let steps = match text.map(|t| script::parse(&t)) {
+        Ok(Ok(steps)) => steps,
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match sim::run(&steps, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! A mecanum chassis driving on what the motor board received
+//!
+//! The motors follow their outputs with a first order lag. Each wheel pushes
+//! the car through its contact patch, in proportion to how much the ground
+//! under it lags behind the rim, and only across its rollers. The car
+//! accelerates with the sum of those forces, so it never looks at
+//! `rcdrive::kinematics` and can tell when those are wrong.
+//!
+//! The body frame has x to the right and y ahead, turns are counterclockwise
+//! positive.
+
+use rcdrive::{
+    heading::{body_to_world, wrap},
+    WheelSpeed,
+};
+use rcproto::{CarConfig, Pose};
+
+/// ground speed of a wheel at full output, in m/s
+pub const FULL_SPEED_MPS: f32 = 0.6;
+/// half the track plus half the wheelbase, in m
+pub const WHEEL_LEVER: f32 = 0.15;
+/// half the track, in m
+const HALF_TRACK: f32 = 0.08;
+/// time constant of the motors, in s
+const MOTOR_LAG: f32 = 0.08;
+/// in kg
+const MASS: f32 = 1.2;
+/// about the center, in kg m^2
+const INERTIA: f32 = 0.012;
+/// force of a wheel per m/s of slip, in N s/m
+const TRACTION: f32 = 15.0;
+/// integration steps per [`Chassis::step`], the traction is stiff
+const SUBSTEPS: usize = 20;
+
+/// the motor board as seen from the bus, the last output of every channel
+pub struct Board {
+    address: u8,
+    outputs: [u8; 256],
+}
+
+impl Board {
+    pub fn new(address: u8) -> Self {
+        Board {
+            address,
+            outputs: [rcdrive::driver::NEUTRAL; 256],
+        }
+    }
+
+    /// takes `[channel, output, 0, 0]` frames addressed to the board
+    pub fn receive(&mut self, writes: impl IntoIterator<Item = (u8, Vec<u8>)>) {
+        for (address, bytes) in writes {
+            if let [channel, output, 0, 0] = bytes[..] {
+                if address == self.address {
+                    self.outputs[channel as usize] = output;
+                }
+            }
+        }
+    }
+
+    pub fn output(&self, channel: u8) -> u8 {
+        self.outputs[channel as usize]
+    }
+
+    /// the speed every wheel is driven at, undoing the calibration of `config`
+    pub fn wheels(&self, config: &CarConfig) -> WheelSpeed {
+        WheelSpeed::from_array(core::array::from_fn(|i| {
+            let cal = &config.calibration[i];
+            let output = self.output(config.motor_channels[i]) as f32;
+            let speed = (output - cal.neutral as f32) / config.speed_scale;
+            if cal.invert {
+                -speed
+            } else {
+                speed
+            }
+        }))
+    }
+}
+
+/// where a wheel touches the ground and which way its rollers let it push
+struct Wheel {
+    /// contact patch in the body frame, in m
+    at: [f32; 2],
+    /// +1 when pushing ahead also pushes right, -1 when it pushes left
+    roller: f32,
+    /// -1 for the right side, whose motors are mounted mirrored
+    mount: f32,
+}
+
+/// in the order of `WheelSpeed::to_array`
+const WHEELS: [Wheel; 4] = {
+    let (a, b) = (HALF_TRACK, WHEEL_LEVER - HALF_TRACK);
+    [
+        Wheel {
+            at: [-a, b],
+            roller: 1.0,
+            mount: 1.0,
+        },
+        Wheel {
+            at: [-a, -b],
+            roller: -1.0,
+            mount: 1.0,
+        },
+        Wheel {
+            at: [a, b],
+            roller: -1.0,
+            mount: -1.0,
+        },
+        Wheel {
+            at: [a, -b],
+            roller: 1.0,
+            mount: -1.0,
+        },
+    ]
+};
+
+#[derive(Default)]
+pub struct Chassis {
+    pub pose: Pose,
+    /// what the wheels actually turn at, behind the driven speed
+    pub wheels: WheelSpeed,
+    /// body velocity `[vx, vy]` in m/s
+    pub velocity: [f32; 2],
+    /// in rad/s, counterclockwise
+    pub turn_rate: f32,
+}
+
+impl Chassis {
+    pub fn step(&mut self, driven: &WheelSpeed, dt: f32) {
+        let follow = (dt / MOTOR_LAG).min(1.0);
+        self.wheels = self.wheels + (*driven + self.wheels * -1.0) * follow;
+
+        let rims = self.wheels.to_array();
+        let h = dt / SUBSTEPS as f32;
+        for _ in 0..SUBSTEPS {
+            let [vx, vy] = self.velocity;
+            let w = self.turn_rate;
+            let (mut fx, mut fy, mut torque) = (0.0, 0.0, 0.0);
+            for (wheel, rim) in WHEELS.iter().zip(rims) {
+                let [px, py] = wheel.at;
+                // the ground under the wheel, moving with the body
+                let (gx, gy) = (vx - w * py, vy + w * px);
+                // the rollers turn freely, the wheel only pushes along (roller, 1)
+                let slip = rim * wheel.mount * FULL_SPEED_MPS - (gy + wheel.roller * gx);
+                let (wx, wy) = (TRACTION * slip * wheel.roller, TRACTION * slip);
+                fx += wx;
+                fy += wy;
+                torque += px * wy - py * wx;
+            }
+            // in a turning frame the velocity turns the other way
+            self.velocity = [vx + (fx / MASS + w * vy) * h, vy + (fy / MASS - w * vx) * h];
+            self.turn_rate += torque / INERTIA * h;
+
+            let turn = self.turn_rate * h;
+            let mid = self.pose.heading + turn / 2.0;
+            let [vx, vy] = self.velocity;
+            let (dx, dy) = body_to_world(vx * h, vy * h, mid);
+            self.pose.x += dx;
+            self.pose.y += dy;
+            self.pose.heading = wrap(self.pose.heading + turn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_ignores_other_addresses() {
+        let mut board = Board::new(0x10);
+        board.receive([(0x10, vec![4, 135, 0, 0]), (0x40, vec![5, 0, 0, 0])]);
+        assert_eq!(board.output(4), 135);
+        assert_eq!(board.output(5), 90);
+        let wheels = board.wheels(&CarConfig::default());
+        assert_eq!(wheels.front_left, 0.5);
+        assert_eq!(wheels.back_left, 0.0);
+    }
+
+    #[test]
+    fn motors_lag_behind() {
+        let mut chassis = Chassis::default();
+        let forward = WheelSpeed::drive_y(1.0);
+        chassis.step(&forward, 0.02);
+        assert!(chassis.wheels.front_left > 0.0 && chassis.wheels.front_left < 0.5);
+        for _ in 0..50 {
+            chassis.step(&forward, 0.02);
+        }
+        assert!((chassis.wheels.front_left - 1.0).abs() < 1e-3);
+        assert!(chassis.pose.y > 0.5);
+    }
+
+    #[test]
+    fn settles_where_the_kinematics_say() {
+        use rcdrive::{kinematics::Mecanum, Kinematics};
+
+        for [vx, vy, wz] in [
+            [0.5, 0.0, 0.0],
+            [0.0, -0.4, 0.0],
+            [0.0, 0.0, 0.6],
+            [0.3, 0.3, 0.2],
+        ] {
+            let wheels = Mecanum.outputs(vx, vy, wz);
+            let mut chassis = Chassis::default();
+            for _ in 0..100 {
+                chassis.step(&wheels, 0.02);
+            }
+            let [expect_x, expect_y, expect_z] = Mecanum.velocity(&wheels);
+            let [x, y] = chassis.velocity.map(|v| v / FULL_SPEED_MPS);
+            let z = chassis.turn_rate * WHEEL_LEVER / FULL_SPEED_MPS;
+            let off = (x - expect_x).abs() + (y - expect_y).abs() + (z - expect_z).abs();
+            // turning while driving takes a little slip for the centripetal force
+            assert!(off < 0.01, "{:?} went {:?}", [vx, vy, wz], [x, y, z]);
+        }
+    }
+}
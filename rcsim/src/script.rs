@@ -0,0 +1,89 @@
+//! Command scripts, one velocity command per line
+//!
+//! `<time s> <x> <y> <z> [headless]`, sent at `time` and held until the next
+//! line. Blank lines and `#` comments are skipped.
+
+use std::fmt;
+
+use rcproto::{Flags, VelocityCommand};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    /// seconds since the start
+    pub at: f32,
+    pub command: VelocityCommand,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+fn parse_line(line: &str) -> Result<Step, &'static str> {
+    let mut words = line.split_whitespace();
+    let mut number = |what| words.next().ok_or(what)?.parse::<f32>().map_err(|_| what);
+    let at = number("bad time")?;
+    let x = number("bad x")?;
+    let y = number("bad y")?;
+    let z = number("bad z")?;
+    let mut command = VelocityCommand::new(x, y, z);
+    match words.next() {
+        None => {}
+        Some("headless") => command.flags.insert(Flags::HEADLESS),
+        Some(_) => return Err("unknown flag"),
+    }
+    if words.next().is_some() {
+        return Err("trailing words");
+    }
+    if [x, y, z].iter().any(|v| !(-1.0..=1.0).contains(v)) {
+        return Err("velocity out of -1..=1");
+    }
+    Ok(Step { at, command })
+}
+
+pub fn parse(text: &str) -> Result<Vec<Step>, ParseError> {
+    let mut steps: Vec<Step> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg| ParseError { line: i + 1, msg };
+        let step = parse_line(line).map_err(err)?;
+        if steps.last().is_some_and(|last| step.at < last.at) {
+            return Err(err("time goes backwards"));
+        }
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_and_skips_comments() {
+        let steps = parse("# warm up\n0 0 0.5 0\n\n1.5 0.2 0 -0.1 headless # strafe\n").unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].command, VelocityCommand::new(0.0, 0.5, 0.0));
+        assert_eq!(steps[1].at, 1.5);
+        assert!(steps[1].command.flags.contains(Flags::HEADLESS));
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let err = |text| parse(text).unwrap_err();
+        assert_eq!(err("0 0 0 0\n1 0 x 0").line, 2);
+        assert_eq!(err("1 0 0 0\n0 0 0 0").msg, "time goes backwards");
+        assert_eq!(err("0 0 2 0").msg, "velocity out of -1..=1");
+        assert_eq!(err("0 0 0 0 sideways").msg, "unknown flag");
+    }
+}
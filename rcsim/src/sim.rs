@@ -0,0 +1,120 @@
+//! The control loop of `rcar::motor`, at the same rate, minus the hardware
+
+use std::io::{self, Write};
+
+use embassy_futures::block_on;
+use rcdrive::{
+    driver::{mock::MockI2c, Supervisor, Wukong},
+    Odometry, Pipeline,
+};
+use rcproto::{CarConfig, MotionLimits, VelocityCommand};
+
+use crate::{
+    physics::{Board, Chassis, FULL_SPEED_MPS, WHEEL_LEVER},
+    script::Step,
+};
+
+/// period of the control loop on the car
+const DT: f32 = 0.02;
+/// time to keep going after the last command, for the car to come to rest
+const SETTLE: f32 = 1.0;
+
+pub fn run(steps: &[Step], out: &mut impl Write) -> io::Result<()> {
+    let config = CarConfig::default();
+    let mut pipeline = Pipeline::new(&config, MotionLimits::default());
+    let model = pipeline.model();
+    let mut supervisor = Supervisor::default();
+    let mut bus = MockI2c::default();
+    let mut board = Board::new(config.i2c_address);
+    let mut chassis = Chassis::default();
+    let mut odometry = Odometry::new(FULL_SPEED_MPS, WHEEL_LEVER);
+
+    writeln!(
+        out,
+        "t,cmd_x,cmd_y,cmd_z,out_fl,out_fr,out_bl,out_br,x,y,heading,odo_x,odo_y,odo_heading"
+    )?;
+    let end = steps.last().map_or(0.0, |s| s.at) + SETTLE;
+    let mut pending = steps.iter().peekable();
+    let mut target = VelocityCommand::default();
+    let mut seq = 0_u16;
+    for tick in 0..=(end / DT).round() as usize {
+        let t = tick as f32 * DT;
+        while let Some(step) = pending.next_if(|s| s.at <= t + DT / 2.0) {
+            // the bytes the controller would send, numbered and stamped
+            let mut sent = step.command;
+            sent.seq = seq;
+            sent.sent_us = (step.at * 1e6) as u32;
+            seq = seq.wrapping_add(1);
+            target =
+                VelocityCommand::decode(&sent.encode()).expect("scripted commands are in range");
+        }
+
+        // a perfect compass, no obstacles and no encoders
+        let tick = pipeline.tick(&target, chassis.pose.heading, false, DT, |w| w);
+        let bufs = tick.bufs;
+        let mut driver = Wukong::new(&mut bus, config.i2c_address);
+        block_on(supervisor.write(&mut driver, &bufs)).expect("the mock bus never fails");
+        board.receive(bus.writes.drain(..));
+
+        chassis.step(&board.wheels(&config), DT);
+        let odo = odometry.update(model, &tick.wheels, DT);
+
+        let [fl, fr, bl, br] = bufs.map(|[channel, _]| board.output(channel));
+        let pose = chassis.pose;
+        writeln!(
+            out,
+            "{t:.2},{:.3},{:.3},{:.3},{fl},{fr},{bl},{br},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}",
+            target.x, target.y, target.z, pose.x, pose.y, pose.heading, odo.x, odo.y, odo.heading,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script;
+
+    fn last_row(script: &str) -> Vec<f32> {
+        let mut csv = Vec::new();
+        run(&script::parse(script).unwrap(), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let last = csv.lines().last().unwrap();
+        last.split(',').map(|v| v.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn drives_forward_and_stops() {
+        let row = last_row("0 0 0.5 0\n2 0 0 0\n");
+        let (x, y, heading) = (row[8], row[9], row[10]);
+        // outputs are whole steps, the two sides round apart while ramping
+        assert!(
+            x.abs() < 0.01 && heading.abs() < 0.02,
+            "x {x} heading {heading}"
+        );
+        // about 2s at 0.3m/s, minus the ramps
+        assert!(y > 0.5 && y < 0.6, "y {y}");
+        // stopped wheels sit at neutral
+        assert_eq!(&row[4..8], &[90.0; 4]);
+    }
+
+    #[test]
+    fn headless_holds_the_world_direction_while_turning() {
+        let row = last_row("0 0 0.3 0.3 headless\n2 0 0 0\n");
+        let (x, heading) = (row[8], row[10]);
+        assert!(heading > 0.5, "heading {heading}");
+        assert!(x.abs() < 0.05, "x {x}");
+    }
+
+    #[test]
+    fn square_matches_the_recorded_trajectory() {
+        let mut csv = Vec::new();
+        let steps = script::parse(include_str!("../scripts/square.txt")).unwrap();
+        run(&steps, &mut csv).unwrap();
+        // regenerate with `cargo run --target <host> -- scripts/square.txt > scripts/square.csv`
+        assert!(
+            String::from_utf8(csv).unwrap() == include_str!("../scripts/square.csv"),
+            "trajectory of scripts/square.txt changed"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH                             : ORIGIN = 0x00000000 + 156K , LENGTH = 405504
+  FLASH                             : ORIGIN = 0x00000000 + 156K , LENGTH = 360448
+  /* the last page, 0x7F000..0x80000, holds the paired car, see pairing.rs */
   RAM                               : ORIGIN = 0x20005a08, LENGTH = 86104
 }
@@ -1,20 +1,30 @@
 #![no_std]
 #![no_main]
 
+pub mod link;
+pub mod pairing;
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::join::join3;
+use embassy_futures::select::{Either4, select4};
 use embassy_nrf::config::Config;
 use embassy_nrf::interrupt::Priority;
 use embassy_sync::signal::Signal;
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant, Timer};
 use micromath::F32;
-use nrf_softdevice::ble::{Address, AddressType, central, gatt_client};
-use nrf_softdevice::{Softdevice, raw};
+use nrf_softdevice::ble::{central, gatt_client};
+use nrf_softdevice::{Flash, Softdevice, raw};
 
-use array_concat::*;
 use core::mem;
 use defmt::{info, *};
 use micromath::F32Ext;
+use rcproto::{
+    CONTROL_REQUEST_LEN, ControlRequest, ECHO_LEN, Echo, Faults, Flags, LATENCY_LEN, LatencyStats,
+    VELOCITY_LEN, VelocityCommand,
+};
 
 /// Application must run at a lower priority than softdevice
 pub fn config() -> Config {
@@ -36,11 +46,38 @@ async fn softdevice_task(sd: &'static Softdevice) -> ! {
 //     battery_level: u8,
 // }
 
+/// uuids must match `rcproto::SERVICE_UUID_STR` and friends
 #[nrf_softdevice::gatt_client(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a30")]
 struct RcCarClient {
-    ///speed forward m/s
+    /// encoded `rcproto::VelocityCommand`
     #[characteristic(uuid = "2C09", write, read)]
-    target_velocity: [u8; 4 * 3],
+    target_velocity: [u8; VELOCITY_LEN],
+    /// latched `rcproto::Faults`, any write clears them
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a33", read, write)]
+    faults: u8,
+    /// any write makes the current heading the zero of headless driving
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a36", write)]
+    zero_heading: u8,
+    /// an encoded `rcproto::ControlRequest`
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a40", write)]
+    control: [u8; CONTROL_REQUEST_LEN],
+    /// encoded `rcproto::Echo` of the latest velocity command the car took
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a43", read, notify)]
+    echo: [u8; ECHO_LEN],
+    /// encoded `rcproto::LatencyReport`, what we measured, for others to read
+    #[characteristic(uuid = "8a8ec266-3ede-4a2f-a87b-aafbc55b8a44", write, read)]
+    latency: [u8; LATENCY_LEN],
+}
+
+/// how often control is claimed, the car only takes it once the link is
+/// bonded and nobody else drives
+const CLAIM_PERIOD: Duration = Duration::from_secs(1);
+/// how often the round trip numbers are logged and posted to the car
+const LATENCY_PERIOD: Duration = Duration::from_secs(5);
+
+/// our clock as the car echoes it back, wraps after about 71 minutes
+fn now_us() -> u32 {
+    Instant::now().as_micros() as u32
 }
 
 fn sd_config() -> &'static Softdevice {
@@ -64,7 +101,7 @@ fn sd_config() -> &'static Softdevice {
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: 1,
             periph_role_count: 3,
-            central_sec_count: 0,
+            central_sec_count: 1,
             central_role_count: 3,
             _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
         }),
@@ -88,16 +125,28 @@ pub type Vec3 = micromath::vector::F32x3;
 
 pub type SharedSpeed = Signal<ThreadModeRawMutex, Vec3>;
 
+/// drive relative to the zeroed heading instead of the car
+pub static HEADLESS: AtomicBool = AtomicBool::new(false);
+/// asks the car to take its current heading as zero
+pub static ZERO_HEADING: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// `forget` drops the paired car and pairs with the closest one instead
 #[embassy_executor::task]
-pub async fn write_ble(target_speed: &'static SharedSpeed, s: Spawner) {
+pub async fn write_ble(target_speed: &'static SharedSpeed, s: Spawner, forget: bool) {
     let sd = sd_config();
+    let car = if forget { None } else { pairing::load() };
+    let flash = Flash::take(sd);
     s.spawn(softdevice_task(&sd)).unwrap();
+    s.spawn(pairing::store_car(flash, forget)).unwrap();
 
-    let addrs = &[&Address::new(
-        AddressType::RandomStatic,
-        [0x13, 0x33, 0x33, 0x33, 0x37, 0b1100_1010],
-        // [0x06, 0x6b, 0x71, 0x2c, 0xf5, 0xc0],
-    )];
+    let address = match car {
+        Some(car) => car.address,
+        None => {
+            info!("no paired car, looking for one close by");
+            pairing::find_car(sd).await
+        }
+    };
+    let addrs = &[&address];
     let mut config = central::ConnectConfig::default();
     // info!("central config: {:#?}", config.);
     info!("looking for device: {}", addrs);
@@ -115,39 +164,150 @@ pub async fn write_ble(target_speed: &'static SharedSpeed, s: Spawner) {
     )
     .await
     .unwrap();
-    let conn = central::connect(sd, &config).await.unwrap();
+    config.conn_params = link::conn_params(rcproto::INTERVAL_STEPS[0]);
+    let mut conn = central::connect_with_security(sd, &config, &pairing::PAIRING)
+        .await
+        .unwrap();
     info!("connected");
+    // a paired car encrypts with the stored keys, a new one shows a passkey
+    if let Err(e) = conn.request_security() {
+        error!("failed to request security: {}", e);
+    }
 
     let client: RcCarClient = unwrap!(gatt_client::discover(&conn).await);
-    let mut last_speed = Vec3::default();
-    let epsillon = 0.04_f32.powi(2);
-    loop {
-        let speed = target_speed.wait().await;
-
-        let diff_speed = (speed - last_speed);
-        let dlen2 = diff_speed[0].powi(2) + diff_speed[1].powi(2) + diff_speed[2].powi(2);
-        if dlen2 < epsillon {
-            continue;
+    link::tune(&mut conn).await;
+
+    if let Err(e) = client.echo_cccd_write(true).await {
+        error!("failed to subscribe to echoes: {}", e);
+    }
+
+    let stats = RefCell::new(LatencyStats::new());
+    let echoes = gatt_client::run(&conn, &client, |event| match event {
+        RcCarClientEvent::EchoNotification(bytes) => match Echo::decode(&bytes) {
+            Ok(echo) => {
+                if let Some(rtt) = stats.borrow_mut().echoed(&echo, now_us()) {
+                    trace!("round trip of {}: {}us", echo.seq, rtt);
+                }
+            }
+            Err(e) => warn!("bad echo: {}", e),
+        },
+    });
+    let reporting = async {
+        loop {
+            Timer::after(LATENCY_PERIOD).await;
+            let report = stats.borrow_mut().report();
+            info!(
+                "round trip {}us, jitter {}us, max {}us, lost {} of {} ({}%)",
+                report.rtt_us,
+                report.jitter_us,
+                report.max_rtt_us,
+                report.lost,
+                report.sent,
+                report.loss_percent()
+            );
+            if let Err(e) = client.latency_write(&report.encode()).await {
+                error!("failed to post latency: {}", e);
+            }
         }
-        info!(
-            "new speed: {:?}, old: {:?}",
-            speed.to_array(),
-            last_speed.to_array()
-        );
+    };
+    let driving = async {
+        let mut last_speed = Vec3::default();
+        let epsillon = 0.04_f32.powi(2);
+        // the car stops if it doesn't hear from us, so repeat unchanged speeds
+        let keepalive = Duration::from_millis(rcproto::KEEPALIVE_MS as u64);
+        let mut last_sent = Instant::now();
+        let mut last_claim = Instant::MIN;
+        let mut faults_checked = false;
+        let mut seq = 0_u16;
+        loop {
+            let speed = match select4(
+                target_speed.wait(),
+                Timer::at(last_sent + keepalive),
+                ZERO_HEADING.wait(),
+                Timer::at(last_claim + CLAIM_PERIOD),
+            )
+            .await
+            {
+                Either4::First(speed) => {
+                    let diff_speed = (speed - last_speed);
+                    let dlen2 =
+                        diff_speed[0].powi(2) + diff_speed[1].powi(2) + diff_speed[2].powi(2);
+                    if dlen2 < epsillon {
+                        continue;
+                    }
+                    info!(
+                        "new speed: {:?}, old: {:?}",
+                        speed.to_array(),
+                        last_speed.to_array()
+                    );
+                    speed
+                }
+                Either4::Second(()) => last_speed,
+                Either4::Third(()) => {
+                    info!("zeroing heading");
+                    if let Err(e) = client.zero_heading_write(&1).await {
+                        error!("failed to zero heading: {}", e);
+                    }
+                    continue;
+                }
+                Either4::Fourth(()) => {
+                    last_claim = Instant::now();
+                    let claim = ControlRequest::Claim.encode();
+                    if let Err(e) = client.control_write(&claim).await {
+                        error!("failed to claim control: {}", e);
+                    }
+                    if !faults_checked {
+                        faults_checked = clear_faults(&client).await;
+                    }
+                    continue;
+                }
+            };
 
-        last_speed = speed;
+            last_speed = speed;
+            last_sent = Instant::now();
 
-        let x_bytes = speed.x.to_le_bytes();
-        let y_bytes = speed.y.to_le_bytes();
-        let z_bytes = speed.z.to_le_bytes();
-        let v_bytes = concat_arrays!(x_bytes, y_bytes, z_bytes);
+            // the car refuses anything outside -1..=1, a stick off its
+            // calibration can get there
+            let [x, y, z] = speed.to_array().map(|v| v.clamp(-1.0, 1.0));
+            let mut cmd = VelocityCommand::new(x, y, z);
+            cmd.seq = seq;
+            cmd.sent_us = now_us();
+            seq = seq.wrapping_add(1);
+            if HEADLESS.load(Ordering::Relaxed) {
+                cmd.flags.insert(Flags::HEADLESS);
+            }
+            let v_bytes = cmd.encode();
 
-        match client
-            .target_velocity_write_without_response(&v_bytes)
-            .await
-        {
-            Ok(()) => trace!("sent speed: {:?}", speed),
-            Err(e) => error!("failed to send speedy: {}", e),
-        };
+            match client
+                .target_velocity_write_without_response(&v_bytes)
+                .await
+            {
+                Ok(()) => trace!("sent speed: {:?}", speed),
+                Err(e) => error!("failed to send speedy: {}", e),
+            };
+        }
+    };
+    join3(echoes, reporting, driving).await;
+}
+
+/// clears what the car latched before we connected, whether the faults could
+/// be read
+///
+/// The faults need a bond, the read fails until pairing is done, so this is
+/// retried with every claim.
+async fn clear_faults(client: &RcCarClient) -> bool {
+    match client.faults_read().await {
+        Ok(0) => true,
+        Ok(bits) => {
+            warn!("car reports faults: {}", Faults::from_bits(bits));
+            if let Err(e) = client.faults_write(&0).await {
+                error!("failed to clear faults: {}", e);
+            }
+            true
+        }
+        Err(e) => {
+            debug!("faults not readable yet: {}", e);
+            false
+        }
     }
 }
@@ -0,0 +1,70 @@
+//! Tuning the connection to the car, see `rcproto::link`
+//!
+//! As the central the controller sets the connection parameters itself. The
+//! car asks for the same ones, but the softdevice may still fall back to a
+//! slower interval, so the steps are tried fastest first. Which PHY and
+//! packet length the car agreed to isn't reported by the softdevice, those
+//! are logged as unknown once asked for, or as the defaults when asking
+//! failed.
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+use nrf_softdevice::ble::{Connection, Phy};
+use nrf_softdevice::raw;
+use rcproto::{LinkParams, LinkPhy};
+
+/// how long an update gets to take effect
+const NEGOTIATION_TIME: Duration = Duration::from_millis(500);
+
+/// the connection parameters of `step`, see `rcproto::INTERVAL_STEPS`
+pub fn conn_params((min, max): (u16, u16)) -> raw::ble_gap_conn_params_t {
+    raw::ble_gap_conn_params_t {
+        min_conn_interval: min,
+        max_conn_interval: max,
+        slave_latency: rcproto::SLAVE_LATENCY,
+        conn_sup_timeout: rcproto::SUPERVISION_TIMEOUT,
+    }
+}
+
+/// gets `conn` onto 2M with long packets and the fastest interval that sticks
+pub async fn tune(conn: &mut Connection) -> LinkParams {
+    let phy = match conn.phy_update(Phy::M2, Phy::M2) {
+        // the softdevice doesn't say what the peer agreed to
+        Ok(()) => LinkPhy::Unknown,
+        Err(e) => {
+            warn!("2M phy refused, staying on 1M: {}", e);
+            LinkPhy::M1
+        }
+    };
+    let data_length = match conn.data_length_update(None) {
+        Ok(_) => rcproto::DATA_LENGTH_UNKNOWN,
+        Err(e) => {
+            warn!("data length extension refused: {}", e);
+            rcproto::DATA_LENGTH_DEFAULT
+        }
+    };
+    let current = |conn: &Connection| {
+        let now = conn.conn_params();
+        LinkParams {
+            interval: now.max_conn_interval,
+            latency: now.slave_latency,
+            timeout: now.conn_sup_timeout,
+            phy,
+            data_length,
+        }
+    };
+    let mut link = current(conn);
+    for step in rcproto::INTERVAL_STEPS {
+        if link.meets(step) {
+            break;
+        }
+        if let Err(e) = conn.set_conn_params(conn_params(step)) {
+            warn!("interval {} refused: {}", step, e);
+            continue;
+        }
+        Timer::after(NEGOTIATION_TIME).await;
+        link = current(conn);
+    }
+    info!("link to the car every {}us, {}", link.interval_us(), link);
+    link
+}
@@ -6,7 +6,7 @@ use embassy_executor::Spawner;
 use embassy_futures::select::{Either, select};
 use embassy_nrf::{
     bind_interrupts,
-    gpio::{AnyPin, Input, Level, Output, OutputDrive, Pin},
+    gpio::{AnyPin, Input, Level, Output, OutputDrive, Pin, Pull},
     interrupt::{self, InterruptExt, Priority},
     peripherals::{self, P0_00, P0_02, P0_03, P0_04, P0_05, P0_31, SAADC},
     saadc::{self, Saadc},
@@ -17,8 +17,9 @@ use micromath::F32Ext;
 
 use embassy_time::{Duration, Timer};
 // use microbit_bsp::*;
+use core::sync::atomic::Ordering;
 use nrf_softdevice;
-use rctrl::{SharedSpeed, Vec2, Vec3, write_ble};
+use rctrl::{HEADLESS, SharedSpeed, Vec2, Vec3, ZERO_HEADING, pairing, write_ble};
 use {defmt_rtt as _, panic_probe as _};
 
 type Btn = Input<'static, AnyPin>;
@@ -129,6 +130,26 @@ async fn analog_read(
     }
 }
 
+/// button a toggles headless driving, button b zeroes the heading, while
+/// pairing they type in the passkey
+#[embassy_executor::task]
+async fn buttons(mut a: Btn, mut b: Btn) {
+    loop {
+        match select(a.wait_for_falling_edge(), b.wait_for_falling_edge()).await {
+            Either::First(()) if pairing::entering() => pairing::count_up(),
+            Either::Second(()) if pairing::entering() => pairing::next_digit(),
+            Either::First(()) => {
+                let headless = !HEADLESS.load(Ordering::Relaxed);
+                HEADLESS.store(headless, Ordering::Relaxed);
+                info!("headless: {}", headless);
+            }
+            Either::Second(()) => ZERO_HEADING.signal(()),
+        }
+        // debounce
+        Timer::after_millis(50).await;
+    }
+}
+
 #[embassy_executor::main]
 async fn main(s: Spawner) {
     let mut p = embassy_nrf::init(rctrl::config());
@@ -141,5 +162,10 @@ async fn main(s: Spawner) {
         p.P0_31,
     ))
     .unwrap();
-    s.spawn(write_ble(&TARGET_SPEED, s)).unwrap();
+    let btn_a = Input::new(p.P0_14.degrade(), Pull::Up);
+    let btn_b = Input::new(p.P0_23.degrade(), Pull::Up);
+    // held while powering up
+    let forget = btn_b.is_low();
+    s.spawn(buttons(btn_a, btn_b)).unwrap();
+    s.spawn(write_ble(&TARGET_SPEED, s, forget)).unwrap();
 }
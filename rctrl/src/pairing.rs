@@ -0,0 +1,253 @@
+//! Pairing with a car, and remembering which one it was
+//!
+//! Without a stored car the controller listens for cars that advertise
+//! `RcCarService` for a while and connects to the loudest one, if it is close
+//! by. The car scrolls a passkey over its LED matrix, which is typed in with
+//! the buttons: A counts the current digit up, B takes it and moves on to the
+//! next. Once bonded, the address and keys of the car go to the last flash
+//! page, see `memory.x`, and later boots only connect to that car. Holding B
+//! while powering up forgets it.
+
+use core::cell::{Cell, RefCell};
+
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use embedded_storage_async::nor_flash::NorFlash;
+use nrf_softdevice::ble::security::{IoCapabilities, PasskeyReply, SecurityHandler};
+use nrf_softdevice::ble::{
+    Address, Connection, EncryptionInfo, IdentityKey, MasterId, SecurityMode, central,
+};
+use nrf_softdevice::{Flash, Softdevice};
+use rcproto::RecordError;
+
+/// start of the page reserved in `memory.x`
+const CAR_PAGE: u32 = 0x7F000;
+const PAGE_SIZE: u32 = 4096;
+const MAGIC: [u8; 4] = *b"RCAR";
+/// address type, address, ediv, rand, ltk and key flags
+const CAR_LEN: usize = 1 + 6 + 2 + 8 + 16 + 1;
+const RECORD_LEN: usize = rcproto::record_len(CAR_LEN);
+/// a car heard at least this loud is close enough to pair with, in dBm
+const CLOSE_RSSI: i8 = -55;
+/// how long to listen for cars before picking the loudest
+const SCAN_WINDOW: Duration = Duration::from_secs(3);
+
+/// the car this controller is bonded with
+#[derive(Clone, Copy)]
+pub struct Car {
+    pub address: Address,
+    master_id: MasterId,
+    key: EncryptionInfo,
+}
+
+impl Car {
+    fn encode(&self) -> [u8; CAR_LEN] {
+        let mut buf = [0; CAR_LEN];
+        buf[0] = self.address.address_type() as u8;
+        buf[1..7].copy_from_slice(&self.address.bytes());
+        buf[7..9].copy_from_slice(&self.master_id.ediv.to_le_bytes());
+        buf[9..17].copy_from_slice(&self.master_id.rand);
+        buf[17..33].copy_from_slice(&self.key.ltk);
+        buf[33] = self.key.flags;
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Car> {
+        Some(Car {
+            address: Address::new(rcproto::address_type!(buf[0])?, buf[1..7].try_into().ok()?),
+            master_id: MasterId {
+                ediv: u16::from_le_bytes([buf[7], buf[8]]),
+                rand: buf[9..17].try_into().ok()?,
+            },
+            key: EncryptionInfo {
+                ltk: buf[17..33].try_into().ok()?,
+                flags: buf[33],
+            },
+        })
+    }
+}
+
+fn decode_record(record: &[u8]) -> Option<Car> {
+    match rcproto::unframe(MAGIC, record, CAR_LEN) {
+        Ok(body) => Car::decode(body),
+        Err(RecordError::Missing) => None,
+        Err(RecordError::Checksum) => {
+            warn!("paired car checksum mismatch");
+            None
+        }
+    }
+}
+
+static CAR: Mutex<ThreadModeRawMutex, Cell<Option<Car>>> = Mutex::new(Cell::new(None));
+/// a new bond for [`store_car`]
+static BONDED: Signal<ThreadModeRawMutex, Car> = Signal::new();
+
+/// a passkey being typed in
+struct Entry {
+    reply: PasskeyReply,
+    /// ascii digits
+    digits: [u8; 6],
+    at: usize,
+}
+
+static ENTRY: Mutex<ThreadModeRawMutex, RefCell<Option<Entry>>> = Mutex::new(RefCell::new(None));
+
+/// the security handler of the connection to the car
+pub struct Pairing;
+
+pub static PAIRING: Pairing = Pairing;
+
+impl SecurityHandler for Pairing {
+    fn io_capabilities(&self) -> IoCapabilities {
+        IoCapabilities::KeyboardOnly
+    }
+
+    fn can_bond(&self, _conn: &Connection) -> bool {
+        true
+    }
+
+    fn enter_passkey(&self, reply: PasskeyReply) {
+        info!("type in the passkey shown on the car: A counts up, B takes the digit");
+        let entry = Entry {
+            reply,
+            digits: [b'0'; 6],
+            at: 0,
+        };
+        ENTRY.lock(|e| e.replace(Some(entry)));
+    }
+
+    fn on_security_update(&self, _conn: &Connection, security_mode: SecurityMode) {
+        info!("link security: {}", security_mode);
+    }
+
+    fn on_bonded(
+        &self,
+        conn: &Connection,
+        master_id: MasterId,
+        key: EncryptionInfo,
+        _peer_id: IdentityKey,
+    ) {
+        let car = Car {
+            address: conn.peer_address(),
+            master_id,
+            key,
+        };
+        info!("bonded with {}", car.address);
+        CAR.lock(|c| c.set(Some(car)));
+        BONDED.signal(car);
+    }
+
+    fn get_peripheral_key(&self, conn: &Connection) -> Option<(MasterId, EncryptionInfo)> {
+        CAR.lock(|c| c.get())
+            .filter(|car| car.address == conn.peer_address())
+            .map(|car| (car.master_id, car.key))
+    }
+}
+
+/// whether the buttons type in a passkey
+pub fn entering() -> bool {
+    ENTRY.lock(|e| e.borrow().is_some())
+}
+
+/// counts the current digit up, from 9 back to 0
+pub fn count_up() {
+    ENTRY.lock(|e| {
+        if let Some(entry) = e.borrow_mut().as_mut() {
+            let digit = &mut entry.digits[entry.at];
+            *digit = if *digit == b'9' { b'0' } else { *digit + 1 };
+            info!("passkey digit {}: {}", entry.at + 1, *digit as char);
+        }
+    });
+}
+
+/// takes the current digit, after the last one the passkey goes to the car
+pub fn next_digit() {
+    ENTRY.lock(|e| {
+        let mut e = e.borrow_mut();
+        let done = match e.as_mut() {
+            Some(entry) => {
+                entry.at += 1;
+                entry.at == entry.digits.len()
+            }
+            None => return,
+        };
+        if let Some(entry) = e.take_if(|_| done) {
+            info!("sending passkey");
+            if let Err(err) = entry.reply.reply(Some(&entry.digits)) {
+                warn!("failed to send passkey: {}", err);
+            }
+        }
+    });
+}
+
+/// the stored car, if the controller was paired before
+pub fn load() -> Option<Car> {
+    let record = unsafe { rcproto::read_flash(CAR_PAGE, RECORD_LEN) };
+    let car = decode_record(record);
+    CAR.lock(|c| c.set(car));
+    car
+}
+
+/// scans until the loudest car that advertises `RcCarService` within a scan
+/// window is close by
+pub async fn find_car(sd: &Softdevice) -> Address {
+    let mut config = central::ScanConfig::default();
+    // the service uuid is in the scan response
+    config.active = true;
+    loop {
+        let mut loudest: Option<(i8, Address)> = None;
+        let scan = central::scan(sd, &config, |report| {
+            let data = unsafe {
+                core::slice::from_raw_parts(report.data.p_data, report.data.len as usize)
+            };
+            if rcproto::advertises_service(data)
+                && loudest.is_none_or(|(rssi, _)| report.rssi > rssi)
+            {
+                loudest = Some((report.rssi, Address::from_raw(report.peer_addr)));
+            }
+            None::<()>
+        });
+        // the scan only ends within the window when it fails
+        if let Ok(res) = with_timeout(SCAN_WINDOW, scan).await {
+            res.unwrap();
+        }
+        match loudest {
+            Some((rssi, address)) if rssi >= CLOSE_RSSI => {
+                info!("found a car close by: {} at {}dBm", address, rssi);
+                return address;
+            }
+            Some((rssi, address)) => {
+                warn!(
+                    "closest car {} is at {}dBm, hold the controller next to it",
+                    address, rssi
+                );
+            }
+            None => warn!("no car found, is it powered up?"),
+        }
+    }
+}
+
+/// writes the car to flash once bonded, `forget` erases the stored one first
+#[embassy_executor::task]
+pub async fn store_car(mut flash: Flash, forget: bool) {
+    if forget {
+        match flash.erase(CAR_PAGE, CAR_PAGE + PAGE_SIZE).await {
+            Ok(()) => info!("forgot the paired car"),
+            Err(e) => warn!("failed to forget the paired car: {}", e),
+        }
+    }
+    loop {
+        let car = BONDED.wait().await;
+        let record: [u8; RECORD_LEN] = rcproto::frame(MAGIC, &car.encode());
+        let res = match flash.erase(CAR_PAGE, CAR_PAGE + PAGE_SIZE).await {
+            Ok(()) => flash.write(CAR_PAGE, &record).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => info!("stored the paired car"),
+            Err(e) => warn!("failed to store the paired car: {}", e),
+        }
+    }
+}